@@ -1,4 +1,5 @@
 #![feature(test)]
+#![allow(unused_variables, clippy::unnecessary_cast)]
 
 extern crate smartcrop;
 extern crate test;
@@ -85,3 +86,64 @@ fn bench_find_best_crop(b: &mut Bencher) {
         analyzer.find_best_crop(&image, eight, eight).unwrap();
     });
 }
+
+fn photo_like(width: u32, height: u32) -> RgbBuffer<'static> {
+    let mut data = Vec::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let face = (x as i64 - 500).pow(2) + (y as i64 - 250).pow(2) < 120 * 120;
+            let pixel = if face {
+                [234, 171, 132]
+            } else {
+                [
+                    (x % 97) as u8 * 2,
+                    (y % 89) as u8 * 2,
+                    ((x + y) % 61) as u8 * 4,
+                ]
+            };
+            data.extend_from_slice(&pixel);
+        }
+    }
+    RgbBuffer::new(data, width, height, PixelLayout::Rgb).unwrap()
+}
+
+#[bench]
+fn bench_find_best_crop_prescaled(b: &mut Bencher) {
+    let image = photo_like(1200, 800);
+    let analyzer = Analyzer::new(CropSettings::default());
+    let size = std::num::NonZeroU32::new(200).unwrap();
+
+    b.iter(|| {
+        analyzer.find_best_crop(&image, size, size).unwrap();
+    });
+}
+
+#[bench]
+fn bench_find_best_crop_full_resolution(b: &mut Bencher) {
+    // Small enough to be analysed without downscaling
+    let image = photo_like(600, 400);
+    let analyzer = Analyzer::new(CropSettings::default());
+    let size = std::num::NonZeroU32::new(300).unwrap();
+
+    b.iter(|| {
+        analyzer.find_best_crop(&image, size, size).unwrap();
+    });
+}
+
+#[bench]
+fn bench_find_best_crop_coarse_to_fine(b: &mut Bencher) {
+    let image = photo_like(1200, 800);
+    let settings = CropSettings {
+        candidate_search: CandidateSearch::CoarseToFine {
+            coarse_step: std::num::NonZeroU32::new(32).unwrap(),
+            top_k: std::num::NonZeroU32::new(4).unwrap(),
+        },
+        ..CropSettings::default()
+    };
+    let analyzer = Analyzer::new(settings);
+    let size = std::num::NonZeroU32::new(200).unwrap();
+
+    b.iter(|| {
+        analyzer.find_best_crop(&image, size, size).unwrap();
+    });
+}
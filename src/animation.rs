@@ -0,0 +1,156 @@
+extern crate gif;
+extern crate image as image_ext;
+
+use self::image_ext::{Frame, ImageBuffer};
+use super::{Analyzer, Crop, CropSettings, Error, ScoredCrop};
+use std::num::NonZeroU32;
+
+use self::gif::SetParameter;
+use self::image_ext::ImageDecoder;
+use std::io::{Read, Write};
+
+/// Finds a single crop for all the frames of an animation.
+///
+/// Frames are expected to be full canvases of the same size, as produced by
+/// the decoders of the `image` crate.
+pub fn find_best_animation_crop(
+    frames: &[Frame],
+    width: NonZeroU32,
+    height: NonZeroU32,
+    settings: &CropSettings,
+) -> Result<ScoredCrop, Error> {
+    Analyzer::new(settings.clone()).find_best_crop_for_frames(
+        frames.iter().map(Frame::buffer),
+        width,
+        height,
+    )
+}
+
+/// Crops every frame with the crop found by `find_best_animation_crop`,
+/// keeping the frame delays.
+pub fn crop_animation(
+    frames: Vec<Frame>,
+    width: NonZeroU32,
+    height: NonZeroU32,
+    settings: &CropSettings,
+) -> Result<(Crop, Vec<Frame>), Error> {
+    let crop = find_best_animation_crop(&frames, width, height, settings)?.crop;
+
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let buffer = frame.into_buffer();
+            let cropped = ImageBuffer::from_fn(crop.width, crop.height, |x, y| {
+                *buffer.get_pixel(crop.x + x, crop.y + y)
+            });
+            Frame::from_parts(cropped, 0, 0, delay)
+        })
+        .collect();
+
+    Ok((crop, frames))
+}
+
+/// Reads an animated GIF, crops all of its frames with a single crop and
+/// writes the result as an endlessly looping GIF with the original delays.
+///
+/// Animated PNGs are not supported, the `image` crate decodes only their
+/// first frame.
+pub fn crop_gif<R: Read, W: Write>(
+    input: R,
+    output: W,
+    width: NonZeroU32,
+    height: NonZeroU32,
+    settings: &CropSettings,
+) -> Result<Crop, Error> {
+    let frames = image_ext::gif::Decoder::new(input)
+        .into_frames()
+        .map_err(|e| Error::DecodingFailed(e.to_string()))?
+        .collect();
+
+    let (crop, frames) = crop_animation(frames, width, height, settings)?;
+
+    let encoding_failed = |e: std::io::Error| Error::EncodingFailed(e.to_string());
+    let mut encoder = gif::Encoder::new(output, crop.width as u16, crop.height as u16, &[])
+        .map_err(encoding_failed)?;
+    encoder
+        .set(gif::Repeat::Infinite)
+        .map_err(encoding_failed)?;
+
+    for frame in frames {
+        // Delays are stored in milliseconds, GIF uses hundredths of a second
+        let delay = frame.delay().to_integer() / 10;
+        let mut pixels = frame.into_buffer().into_raw();
+        let mut gif_frame =
+            gif::Frame::from_rgba(crop.width as u16, crop.height as u16, &mut pixels);
+        gif_frame.delay = delay;
+        encoder.write_frame(&gif_frame).map_err(encoding_failed)?;
+    }
+
+    Ok(crop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::image_ext::{Rgba, RgbaImage};
+    use super::*;
+
+    fn frame(skin_x: u32, delay: u16) -> Frame {
+        let buffer: RgbaImage = ImageBuffer::from_fn(48, 16, |x, _| {
+            if x >= skin_x && x < skin_x + 8 {
+                Rgba([255, 200, 159, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        Frame::from_parts(buffer, 0, 0, delay.into())
+    }
+
+    #[test]
+    fn crop_animation_keeps_delays() {
+        let frames = vec![frame(0, 100), frame(32, 250)];
+        let size = NonZeroU32::new(16).unwrap();
+
+        let (crop, cropped) = crop_animation(frames, size, size, &CropSettings::default()).unwrap();
+
+        assert_eq!((crop.width, crop.height), (16, 16));
+        assert_eq!(cropped.len(), 2);
+        assert_eq!(cropped[0].delay(), 100.into());
+        assert_eq!(cropped[1].delay(), 250.into());
+        assert_eq!(cropped[1].buffer().dimensions(), (16, 16));
+    }
+
+    #[test]
+    fn crop_gif_round_trip() {
+        let mut input = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut input, 48, 16, &[]).unwrap();
+            for (skin_x, delay) in &[(0, 10), (32, 25), (40, 5)] {
+                let mut pixels = frame(*skin_x, 0).into_buffer().into_raw();
+                let mut gif_frame = gif::Frame::from_rgba(48, 16, &mut pixels);
+                gif_frame.delay = *delay;
+                encoder.write_frame(&gif_frame).unwrap();
+            }
+        }
+        let mut output = Vec::new();
+        let size = NonZeroU32::new(16).unwrap();
+
+        let crop = crop_gif(
+            &input[..],
+            &mut output,
+            size,
+            size,
+            &CropSettings::default(),
+        )
+        .unwrap();
+
+        let frames: Vec<_> = image_ext::gif::Decoder::new(&output[..])
+            .into_frames()
+            .unwrap()
+            .collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].buffer().dimensions(), (crop.width, crop.height));
+        assert_eq!(frames[1].delay(), 250.into());
+        assert_eq!(frames[2].delay(), 50.into());
+    }
+}
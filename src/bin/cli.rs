@@ -6,6 +6,7 @@ extern crate clap;
 
 use clap::{App, Arg};
 use std::num::NonZeroU32;
+use std::path::Path;
 
 extern crate image;
 
@@ -20,9 +21,30 @@ fn main() {
     let file_in = matches.value_of("INPUT").unwrap();
     let file_out = matches.value_of("OUTPUT").unwrap();
 
-    // Use the open function to load an image from a Path.
-    // ```open``` returns a `DynamicImage` on success.
-    let mut img = image::open(file_in).unwrap();
+    let is_gif = |path: &str| {
+        Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+    };
+    if is_gif(file_in) && is_gif(file_out) {
+        let input = std::fs::File::open(file_in).unwrap();
+        let output = std::fs::File::create(file_out).unwrap();
+        let crop = smartcrop::crop_gif(
+            input,
+            output,
+            NonZeroU32::new(10).unwrap(),
+            NonZeroU32::new(10).unwrap(),
+            &CropSettings::default(),
+        )
+        .unwrap();
+
+        println!("{:?}", crop);
+        return;
+    }
+
+    // Load the image turned upright according to its EXIF orientation, so
+    // the crop matches what users see.
+    let (mut img, _orientation) = smartcrop::open_oriented(file_in).unwrap();
 
     let crop = an
         .find_best_crop(
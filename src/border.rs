@@ -0,0 +1,162 @@
+use super::{Crop, Image, RGB};
+
+/// Largest difference of a channel from the border colour that still
+/// counts as border, used by `detect_content_bounds`
+pub const BORDER_TOLERANCE: u8 = 16;
+
+// Fraction of pixels of a border line allowed to differ more, for dust and
+// noise in scans
+const BORDER_OUTLIERS: f64 = 0.01;
+
+/// Area of the image inside uniform borders, letterbox bars and padding.
+///
+/// Returns the whole image when it has no borders or is uniform altogether.
+pub fn detect_content_bounds<I: Image>(img: &I) -> Crop {
+    content_bounds(img, BORDER_TOLERANCE)
+}
+
+pub(crate) fn content_bounds<I: Image>(img: &I, tolerance: u8) -> Crop {
+    let (width, height) = (img.width(), img.height());
+    let whole = Crop {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+    if width == 0 || height == 0 {
+        return whole;
+    }
+
+    let close = |a: RGB, b: RGB| {
+        a.r.abs_diff(b.r) <= tolerance
+            && a.g.abs_diff(b.g) <= tolerance
+            && a.b.abs_diff(b.b) <= tolerance
+    };
+    let uniform = |pixels: &mut dyn Iterator<Item = RGB>, reference: RGB, length: u32| {
+        let allowed = (length as f64 * BORDER_OUTLIERS) as usize;
+        pixels
+            .filter(|&p| !close(p, reference))
+            .nth(allowed)
+            .is_none()
+    };
+    let row =
+        |y: u32, reference: RGB| uniform(&mut (0..width).map(|x| img.get(x, y)), reference, width);
+
+    let reference = img.get(0, 0);
+    let mut top = 0;
+    while top < height && row(top, reference) {
+        top += 1;
+    }
+    if top == height {
+        return whole;
+    }
+
+    let reference = img.get(0, height - 1);
+    let mut bottom = height;
+    while bottom > top + 1 && row(bottom - 1, reference) {
+        bottom -= 1;
+    }
+
+    let column = |x: u32, reference: RGB| {
+        uniform(
+            &mut (top..bottom).map(|y| img.get(x, y)),
+            reference,
+            bottom - top,
+        )
+    };
+
+    let reference = img.get(0, top);
+    let mut left = 0;
+    while left < width - 1 && column(left, reference) {
+        left += 1;
+    }
+
+    let reference = img.get(width - 1, top);
+    let mut right = width;
+    while right > left + 1 && column(right - 1, reference) {
+        right -= 1;
+    }
+
+    Crop {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::RgbBuffer;
+    use super::*;
+
+    fn content(x: u32, y: u32) -> RGB {
+        RGB::new((x * 7 % 256) as u8, (y * 13 % 256) as u8, 90)
+    }
+
+    #[test]
+    fn detects_letterbox_bars() {
+        let img = RgbBuffer::from_fn(120, 80, |x, y| {
+            if (10..70).contains(&y) {
+                content(x, y)
+            } else {
+                RGB::new(0, 0, 0)
+            }
+        });
+
+        assert_eq!(
+            detect_content_bounds(&img),
+            Crop {
+                x: 0,
+                y: 10,
+                width: 120,
+                height: 60,
+            }
+        );
+    }
+
+    #[test]
+    fn tolerates_noise_and_dust_in_borders() {
+        let img = RgbBuffer::from_fn(200, 100, |x, y| {
+            let inside = (30..190).contains(&x) && (5..95).contains(&y);
+            if inside {
+                content(x, y)
+            } else if x == 100 && y == 2 {
+                // Speck of dust
+                RGB::new(0, 0, 0)
+            } else {
+                let noise = ((x * 31 + y * 17) % 9) as u8;
+                RGB::new(240 + noise, 238 + noise, 236 + noise)
+            }
+        });
+
+        assert_eq!(
+            detect_content_bounds(&img),
+            Crop {
+                x: 30,
+                y: 5,
+                width: 160,
+                height: 90,
+            }
+        );
+    }
+
+    #[test]
+    fn images_without_borders_are_kept_whole() {
+        let whole = Crop {
+            x: 0,
+            y: 0,
+            width: 50,
+            height: 40,
+        };
+
+        assert_eq!(
+            detect_content_bounds(&RgbBuffer::from_fn(50, 40, content)),
+            whole
+        );
+        assert_eq!(
+            detect_content_bounds(&RgbBuffer::from_fn(50, 40, |_, _| RGB::new(9, 9, 9))),
+            whole
+        );
+    }
+}
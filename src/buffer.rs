@@ -0,0 +1,274 @@
+use super::resize::box_resize;
+use super::{Error, Image, ResizableImage, ResizeFilter, RGB};
+use std::borrow::Cow;
+
+/// Order of the channels of a single pixel in a raw buffer.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PixelLayout {
+    Rgb,
+    Rgba,
+    Bgr,
+    Bgra,
+}
+
+impl PixelLayout {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelLayout::Rgb | PixelLayout::Bgr => 3,
+            PixelLayout::Rgba | PixelLayout::Bgra => 4,
+        }
+    }
+
+    // Offsets of the red, green and blue channels within a pixel
+    fn offsets(self) -> (usize, usize, usize) {
+        match self {
+            PixelLayout::Rgb | PixelLayout::Rgba => (0, 1, 2),
+            PixelLayout::Bgr | PixelLayout::Bgra => (2, 1, 0),
+        }
+    }
+}
+
+/// Image backed by an owned `Vec<u8>` or a borrowed `&[u8]` of 8-bit pixels.
+///
+/// Rows start every `stride` bytes, so frames with padded rows can be used
+/// without copying. Alpha, if present, is ignored.
+#[derive(Debug, Clone)]
+pub struct RgbBuffer<'a> {
+    width: u32,
+    height: u32,
+    stride: usize,
+    layout: PixelLayout,
+    data: Cow<'a, [u8]>,
+}
+
+impl<'a> RgbBuffer<'a> {
+    /// Creates a buffer with tightly packed rows.
+    pub fn new<D>(data: D, width: u32, height: u32, layout: PixelLayout) -> Result<Self, Error>
+    where
+        D: Into<Cow<'a, [u8]>>,
+    {
+        let stride = (width as usize)
+            .checked_mul(layout.bytes_per_pixel())
+            .ok_or(Error::InvalidStride)?;
+        RgbBuffer::with_stride(data, width, height, stride, layout)
+    }
+
+    /// Creates a buffer whose rows are `stride` bytes apart.
+    pub fn with_stride<D>(
+        data: D,
+        width: u32,
+        height: u32,
+        stride: usize,
+        layout: PixelLayout,
+    ) -> Result<Self, Error>
+    where
+        D: Into<Cow<'a, [u8]>>,
+    {
+        let data = data.into();
+        let row_length = (width as usize)
+            .checked_mul(layout.bytes_per_pixel())
+            .ok_or(Error::InvalidStride)?;
+
+        if stride < row_length {
+            return Err(Error::InvalidStride);
+        }
+        if height > 0 {
+            let length = (stride.checked_mul(height as usize - 1))
+                .and_then(|rows| rows.checked_add(row_length))
+                .ok_or(Error::BufferTooSmall)?;
+            if data.len() < length {
+                return Err(Error::BufferTooSmall);
+            }
+        }
+
+        Ok(RgbBuffer {
+            width,
+            height,
+            stride,
+            layout,
+            data,
+        })
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn layout(&self) -> PixelLayout {
+        self.layout
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_owned(self) -> RgbBuffer<'static> {
+        RgbBuffer {
+            data: Cow::Owned(self.data.into_owned()),
+            ..self
+        }
+    }
+
+    pub(crate) fn from_pixels(width: u32, height: u32, pixels: &[RGB]) -> RgbBuffer<'static> {
+        let mut data = Vec::with_capacity(pixels.len() * 3);
+        for pixel in pixels {
+            data.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+        }
+
+        RgbBuffer {
+            width,
+            height,
+            stride: width as usize * 3,
+            layout: PixelLayout::Rgb,
+            data: Cow::Owned(data),
+        }
+    }
+
+    // Test image with the colour of every pixel given by `generate`
+    #[cfg(test)]
+    pub(crate) fn from_fn<G>(width: u32, height: u32, generate: G) -> RgbBuffer<'static>
+    where
+        G: Fn(u32, u32) -> RGB,
+    {
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(generate(x, y));
+            }
+        }
+        RgbBuffer::from_pixels(width, height, &pixels)
+    }
+}
+
+impl<'a> Image for RgbBuffer<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn get(&self, x: u32, y: u32) -> RGB {
+        let offset = y as usize * self.stride + x as usize * self.layout.bytes_per_pixel();
+        let (r, g, b) = self.layout.offsets();
+
+        RGB::new(
+            self.data[offset + r],
+            self.data[offset + g],
+            self.data[offset + b],
+        )
+    }
+}
+
+/// `ResizableImage` is implemented natively: `Nearest` picks the closest
+/// source pixel, every other filter is approximated with the box filter.
+impl<'a> ResizableImage<RgbBuffer<'static>> for RgbBuffer<'a> {
+    fn resize(&self, width: u32, height: u32) -> RgbBuffer<'static> {
+        box_resize(self, width, height)
+    }
+
+    fn resize_with_filter(
+        &self,
+        width: u32,
+        height: u32,
+        filter: ResizeFilter,
+    ) -> RgbBuffer<'static> {
+        if filter != ResizeFilter::Nearest || self.width == 0 || self.height == 0 {
+            return self.resize(width, height);
+        }
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        let x_ratio = self.width as f64 / width as f64;
+        let y_ratio = self.height as f64 / height as f64;
+        for y in 0..height {
+            let sy = ((y as f64 + 0.5) * y_ratio) as u32;
+            for x in 0..width {
+                let sx = ((x as f64 + 0.5) * x_ratio) as u32;
+                pixels.push(self.get(sx.min(self.width - 1), sy.min(self.height - 1)));
+            }
+        }
+
+        RgbBuffer::from_pixels(width, height, &pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_layout() {
+        let expected = RGB::new(10, 20, 30);
+
+        let rgb = RgbBuffer::new(vec![10, 20, 30], 1, 1, PixelLayout::Rgb).unwrap();
+        let rgba = RgbBuffer::new(vec![10, 20, 30, 255], 1, 1, PixelLayout::Rgba).unwrap();
+        let bgr = RgbBuffer::new(vec![30, 20, 10], 1, 1, PixelLayout::Bgr).unwrap();
+        let bgra = RgbBuffer::new(vec![30, 20, 10, 0], 1, 1, PixelLayout::Bgra).unwrap();
+
+        assert_eq!(rgb.get(0, 0), expected);
+        assert_eq!(rgba.get(0, 0), expected);
+        assert_eq!(bgr.get(0, 0), expected);
+        assert_eq!(bgra.get(0, 0), expected);
+    }
+
+    #[test]
+    fn respects_stride_of_borrowed_data() {
+        let data: &[u8] = &[1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12];
+        let buffer = RgbBuffer::with_stride(data, 2, 2, 8, PixelLayout::Rgb).unwrap();
+
+        assert_eq!(buffer.get(1, 0), RGB::new(4, 5, 6));
+        assert_eq!(buffer.get(0, 1), RGB::new(7, 8, 9));
+        assert_eq!(buffer.get(1, 1), RGB::new(10, 11, 12));
+    }
+
+    #[test]
+    fn rejects_invalid_dimensions() {
+        assert_eq!(
+            Error::BufferTooSmall,
+            RgbBuffer::new(vec![0; 11], 2, 2, PixelLayout::Rgb).unwrap_err()
+        );
+        assert_eq!(
+            Error::InvalidStride,
+            RgbBuffer::with_stride(vec![0; 16], 2, 2, 7, PixelLayout::Rgba).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_dimensions() {
+        assert_eq!(
+            Error::BufferTooSmall,
+            RgbBuffer::with_stride(vec![0; 16], 1, 3, usize::MAX / 2, PixelLayout::Rgb)
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn resizes_empty_buffers() {
+        let empty = RgbBuffer::new(vec![], 0, 0, PixelLayout::Rgb).unwrap();
+
+        for &filter in &[ResizeFilter::Box, ResizeFilter::Nearest] {
+            let resized = empty.resize_with_filter(2, 1, filter);
+            assert_eq!((resized.width(), resized.height()), (2, 1));
+            assert_eq!(resized.get(1, 0), RGB::new(0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn resizes_natively() {
+        let buffer = RgbBuffer::new(
+            vec![0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 255, 0],
+            2,
+            2,
+            PixelLayout::Bgr,
+        )
+        .unwrap();
+
+        let boxed = buffer.resize(1, 1);
+        let nearest = buffer.resize_with_filter(4, 4, ResizeFilter::Nearest);
+
+        assert_eq!(boxed.get(0, 0), RGB::new(0, 64, 64));
+        assert_eq!(nearest.get(3, 0), RGB::new(0, 0, 255));
+        assert_eq!(nearest.get(3, 3), RGB::new(0, 255, 0));
+    }
+}
@@ -0,0 +1,177 @@
+/// Operator computing the detail channel from the lightness
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum EdgeOperator {
+    /// 4-neighbour Laplacian of smartcrop.js
+    #[default]
+    Laplacian,
+    /// Gradient magnitude of the 3x3 Sobel kernels, less sensitive to noise
+    Sobel,
+    /// Gradient magnitude of the 3x3 Scharr kernels, more rotation invariant
+    Scharr,
+}
+
+/// Handling of the pixels on the image border, whose neighbourhood is
+/// partly outside of the image
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum EdgeBorder {
+    /// Border pixels get their lightness as detail, as in smartcrop.js
+    #[default]
+    Lightness,
+    /// Pixels outside repeat the closest border pixel
+    Clamp,
+    /// Pixels outside mirror the ones inside, without repeating the border
+    Reflect,
+}
+
+// Lightness values with coordinates outside resolved by the border handling
+pub(crate) struct Plane<'a> {
+    pub values: &'a [f64],
+    pub width: usize,
+    pub height: usize,
+    pub border: EdgeBorder,
+}
+
+impl Plane<'_> {
+    fn at(&self, x: isize, y: isize) -> f64 {
+        let x = resolve(x, self.width, self.border);
+        let y = resolve(y, self.height, self.border);
+        self.values[y * self.width + x]
+    }
+
+    // Edge strength at the pixel, a step of `d` in lightness gives about `d`
+    pub fn edge(&self, x: usize, y: usize, operator: EdgeOperator) -> f64 {
+        let (x, y) = (x as isize, y as isize);
+        let p = |dx: isize, dy: isize| self.at(x + dx, y + dy);
+
+        match operator {
+            EdgeOperator::Laplacian => p(0, 0) * 4.0 - p(0, -1) - p(-1, 0) - p(1, 0) - p(0, 1),
+            EdgeOperator::Sobel => gradient(p, 1.0, 2.0) / 4.0,
+            EdgeOperator::Scharr => gradient(p, 3.0, 10.0) / 16.0,
+        }
+    }
+}
+
+// Magnitude of a 3x3 gradient with `corner` and `side` weights
+fn gradient<P: Fn(isize, isize) -> f64>(p: P, corner: f64, side: f64) -> f64 {
+    let gx = corner * (p(1, -1) + p(1, 1)) + side * p(1, 0)
+        - corner * (p(-1, -1) + p(-1, 1))
+        - side * p(-1, 0);
+    let gy = corner * (p(-1, 1) + p(1, 1)) + side * p(0, 1)
+        - corner * (p(-1, -1) + p(1, -1))
+        - side * p(0, -1);
+
+    (gx * gx + gy * gy).sqrt()
+}
+
+fn resolve(i: isize, length: usize, border: EdgeBorder) -> usize {
+    let last = length as isize - 1;
+    let i = match border {
+        EdgeBorder::Reflect if last > 0 => {
+            // Mirrors repeat with a period of twice the last index
+            let period = 2 * last;
+            let i = i.rem_euclid(period);
+            if i > last {
+                period - i
+            } else {
+                i
+            }
+        }
+        _ => i.clamp(0, last),
+    };
+    i as usize
+}
+
+// Separable Gaussian blur with a kernel radius of 3 `sigma`
+pub(crate) fn blur(plane: &Plane, sigma: f64) -> Vec<f64> {
+    let radius = (sigma * 3.0).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    let kernel: Vec<f64> = kernel.iter().map(|k| k / sum).collect();
+
+    let convolve = |plane: &Plane, horizontal: bool| {
+        let mut output = Vec::with_capacity(plane.values.len());
+        for y in 0..plane.height as isize {
+            for x in 0..plane.width as isize {
+                let mut value = 0.0;
+                for (k, i) in kernel.iter().zip(-radius..=radius) {
+                    value += k * if horizontal {
+                        plane.at(x + i, y)
+                    } else {
+                        plane.at(x, y + i)
+                    };
+                }
+                output.push(value);
+            }
+        }
+        output
+    };
+
+    let horizontal = convolve(plane, true);
+    convolve(
+        &Plane {
+            values: &horizontal,
+            ..*plane
+        },
+        false,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflect_mirrors_without_repeating_the_border() {
+        let resolved: Vec<usize> = (-3..7)
+            .map(|i| resolve(i, 4, EdgeBorder::Reflect))
+            .collect();
+
+        assert_eq!(resolved, vec![3, 2, 1, 0, 1, 2, 3, 2, 1, 0]);
+        assert_eq!(resolve(-2, 1, EdgeBorder::Reflect), 0);
+        assert_eq!(resolve(-2, 4, EdgeBorder::Clamp), 0);
+        assert_eq!(resolve(5, 4, EdgeBorder::Clamp), 3);
+    }
+
+    #[test]
+    fn gradient_operators_measure_step_height() {
+        // Vertical step of 100 between the second and third column
+        let values: Vec<f64> = (0..16)
+            .map(|i| if i % 4 >= 2 { 100.0 } else { 0.0 })
+            .collect();
+        let plane = Plane {
+            values: &values,
+            width: 4,
+            height: 4,
+            border: EdgeBorder::Clamp,
+        };
+
+        for &operator in &[EdgeOperator::Sobel, EdgeOperator::Scharr] {
+            assert_eq!(plane.edge(1, 0, operator), 100.0);
+            assert_eq!(plane.edge(2, 3, operator), 100.0);
+            assert_eq!(plane.edge(0, 1, operator), 0.0);
+        }
+        assert_eq!(plane.edge(2, 1, EdgeOperator::Laplacian), 100.0);
+    }
+
+    #[test]
+    fn blur_keeps_flat_areas_and_smooths_steps() {
+        let values: Vec<f64> = (0..40)
+            .map(|i| if i % 8 >= 4 { 80.0 } else { 0.0 })
+            .collect();
+        let plane = Plane {
+            values: &values,
+            width: 8,
+            height: 5,
+            border: EdgeBorder::Reflect,
+        };
+
+        let blurred = blur(&plane, 1.0);
+
+        assert!((blurred[0] - 0.0).abs() < 1e-9);
+        assert!((blurred[7] - 80.0).abs() < 1e-9);
+        assert!(blurred[3] > 10.0 && blurred[3] < 40.0);
+        assert!(blurred[4] > 40.0 && blurred[4] < 70.0);
+    }
+}
@@ -0,0 +1,307 @@
+use super::Crop;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Value of the EXIF orientation tag: the transformation turning the stored
+/// raster into the image as it should be displayed.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    /// Mirrored along the top-left to bottom-right diagonal
+    Transpose,
+    /// Rotated 90° clockwise
+    Rotate90,
+    /// Mirrored along the top-right to bottom-left diagonal
+    Transverse,
+    /// Rotated 270° clockwise
+    Rotate270,
+}
+
+impl Orientation {
+    pub fn from_exif(value: u16) -> Option<Orientation> {
+        match value {
+            1 => Some(Orientation::Normal),
+            2 => Some(Orientation::FlipHorizontal),
+            3 => Some(Orientation::Rotate180),
+            4 => Some(Orientation::FlipVertical),
+            5 => Some(Orientation::Transpose),
+            6 => Some(Orientation::Rotate90),
+            7 => Some(Orientation::Transverse),
+            8 => Some(Orientation::Rotate270),
+            _ => None,
+        }
+    }
+
+    /// Whether width and height are swapped when displayed.
+    pub fn swaps_dimensions(self) -> bool {
+        matches!(
+            self,
+            Orientation::Transpose
+                | Orientation::Rotate90
+                | Orientation::Transverse
+                | Orientation::Rotate270
+        )
+    }
+
+    /// Maps a crop of the displayed image to the stored raster, whose size is
+    /// `stored_width` x `stored_height`.
+    pub fn crop_to_stored(self, crop: &Crop, stored_width: u32, stored_height: u32) -> Crop {
+        let (x0, y0) = self.edge_to_stored(crop.x, crop.y, stored_width, stored_height);
+        let (x1, y1) = self.edge_to_stored(
+            crop.x + crop.width,
+            crop.y + crop.height,
+            stored_width,
+            stored_height,
+        );
+
+        Crop {
+            x: x0.min(x1),
+            y: y0.min(y1),
+            width: x0.abs_diff(x1),
+            height: y0.abs_diff(y1),
+        }
+    }
+
+    // Maps a point on the pixel edges, so that empty crops stay empty
+    fn edge_to_stored(self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let (w, h) = (width, height);
+        match self {
+            Orientation::Normal => (x, y),
+            Orientation::FlipHorizontal => (w.saturating_sub(x), y),
+            Orientation::Rotate180 => (w.saturating_sub(x), h.saturating_sub(y)),
+            Orientation::FlipVertical => (x, h.saturating_sub(y)),
+            Orientation::Transpose => (y, x),
+            Orientation::Rotate90 => (y, h.saturating_sub(x)),
+            Orientation::Transverse => (w.saturating_sub(y), h.saturating_sub(x)),
+            Orientation::Rotate270 => (w.saturating_sub(y), x),
+        }
+    }
+}
+
+/// Reads the orientation tag of a JPEG file or of a bare TIFF/EXIF block.
+///
+/// Returns `None` if there is no tag or the data is malformed.
+pub fn read_orientation(data: &[u8]) -> Option<Orientation> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        read_jpeg_orientation(data)
+    } else {
+        read_tiff_orientation(data)
+    }
+}
+
+fn read_jpeg_orientation(data: &[u8]) -> Option<Orientation> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            // Padding
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a payload
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            // Start of scan or end of image, metadata comes before them
+            0xDA | 0xD9 => return None,
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return read_tiff_orientation(&segment[6..]);
+        }
+        pos += 2 + length;
+    }
+
+    None
+}
+
+fn read_tiff_orientation(tiff: &[u8]) -> Option<Orientation> {
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize| {
+        let high = u16_at(offset)? as u32;
+        let low = u16_at(offset + 2)? as u32;
+        Some(if big_endian {
+            high << 16 | low
+        } else {
+            low << 16 | high
+        })
+    };
+
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+    for i in 0..entries {
+        let entry = ifd + 2 + i * 12;
+        if u16_at(entry)? == ORIENTATION_TAG {
+            return Orientation::from_exif(u16_at(entry + 8)?);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiff(big_endian: bool, orientation: u16) -> Vec<u8> {
+        let put16 = |v: u16| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let put32 = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+
+        let mut data = Vec::new();
+        data.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+        data.extend_from_slice(&put16(42));
+        data.extend_from_slice(&put32(8));
+        data.extend_from_slice(&put16(2));
+        // ImageWidth, LONG
+        data.extend_from_slice(&put16(0x0100));
+        data.extend_from_slice(&put16(4));
+        data.extend_from_slice(&put32(1));
+        data.extend_from_slice(&put32(640));
+        // Orientation, SHORT
+        data.extend_from_slice(&put16(ORIENTATION_TAG));
+        data.extend_from_slice(&put16(3));
+        data.extend_from_slice(&put32(1));
+        data.extend_from_slice(&put16(orientation));
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&put32(0));
+        data
+    }
+
+    fn jpeg(exif: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        // APP0 segment before the EXIF one
+        data.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00]);
+        let length = (exif.len() + 8) as u16;
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&length.to_be_bytes());
+        data.extend_from_slice(b"Exif\0\0");
+        data.extend_from_slice(exif);
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn reads_orientation_in_both_byte_orders() {
+        assert_eq!(
+            read_orientation(&jpeg(&tiff(true, 6))),
+            Some(Orientation::Rotate90)
+        );
+        assert_eq!(
+            read_orientation(&jpeg(&tiff(false, 8))),
+            Some(Orientation::Rotate270)
+        );
+        assert_eq!(
+            read_orientation(&tiff(false, 3)),
+            Some(Orientation::Rotate180)
+        );
+    }
+
+    #[test]
+    fn ignores_missing_or_malformed_tags() {
+        assert_eq!(read_orientation(&[0xFF, 0xD8, 0xFF, 0xD9]), None);
+        assert_eq!(read_orientation(&jpeg(&tiff(true, 9))), None);
+        assert_eq!(read_orientation(&jpeg(&tiff(true, 6)[..12])), None);
+        assert_eq!(read_orientation(b"not an image"), None);
+    }
+
+    #[test]
+    fn maps_crops_back_to_stored_raster() {
+        // Stored raster is 40x30, displayed rotated 90° clockwise as 30x40
+        let crop = Crop {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 20,
+        };
+
+        assert_eq!(
+            Orientation::Rotate90.crop_to_stored(&crop, 40, 30),
+            Crop {
+                x: 0,
+                y: 20,
+                width: 20,
+                height: 10,
+            }
+        );
+        assert_eq!(
+            Orientation::Rotate270.crop_to_stored(&crop, 40, 30),
+            Crop {
+                x: 20,
+                y: 0,
+                width: 20,
+                height: 10,
+            }
+        );
+        assert_eq!(
+            Orientation::FlipHorizontal.crop_to_stored(&crop, 40, 30),
+            Crop {
+                x: 30,
+                y: 0,
+                width: 10,
+                height: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn maps_empty_crops_to_empty_crops() {
+        let empty = Crop {
+            x: 30,
+            y: 0,
+            width: 0,
+            height: 20,
+        };
+
+        let mapped = Orientation::Rotate90.crop_to_stored(&empty, 40, 30);
+
+        assert_eq!((mapped.width, mapped.height), (20, 0));
+        assert_eq!(
+            Orientation::Rotate180.crop_to_stored(&Crop { height: 0, ..empty }, 0, 0),
+            Crop {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,931 @@
+use super::{Crop, Error, Image, RegionDetector};
+use std::path::Path;
+
+// Longer side of the grayscale image windows are searched in
+const DETECTION_SIZE: f64 = 640.0;
+
+/// Viola-Jones detector evaluating an OpenCV Haar cascade, like the
+/// `haarcascade_frontalface_default.xml` shipped with OpenCV.
+///
+/// Only cascades in the format written by `opencv_traincascade` with
+/// upright features are supported.
+#[derive(Clone, Debug)]
+pub struct HaarCascade {
+    /// Growth of the window size between the scales searched
+    pub scale_factor: f64,
+    /// A detection needs more overlapping windows than this, more rejects
+    /// more false positives. 0 returns every window, as in OpenCV
+    pub min_neighbors: usize,
+    window: (u32, u32),
+    stages: Vec<Stage>,
+    features: Vec<Feature>,
+}
+
+#[derive(Clone, Debug)]
+struct Stage {
+    threshold: f64,
+    classifiers: Vec<Classifier>,
+}
+
+// Decision tree, leaves are referenced by non-positive node indices
+#[derive(Clone, Debug)]
+struct Classifier {
+    nodes: Vec<Node>,
+    leaves: Vec<f64>,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    left: i32,
+    right: i32,
+    feature: usize,
+    threshold: f64,
+}
+
+#[derive(Clone, Debug)]
+struct Feature {
+    rects: Vec<WeightedRect>,
+}
+
+#[derive(Clone, Debug)]
+struct WeightedRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    weight: f64,
+}
+
+impl HaarCascade {
+    /// Loads a cascade XML file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<HaarCascade, Error> {
+        let xml =
+            std::fs::read_to_string(path).map_err(|e| Error::InvalidCascade(e.to_string()))?;
+        HaarCascade::from_xml(&xml)
+    }
+
+    pub fn from_xml(xml: &str) -> Result<HaarCascade, Error> {
+        parse_cascade(xml).map_err(Error::InvalidCascade)
+    }
+
+    /// Windows containing the object with the number of overlapping raw
+    /// detections supporting them, most supported first.
+    pub fn detect_objects(&self, img: &dyn Image) -> Vec<(Crop, usize)> {
+        let (width, height) = (img.width(), img.height());
+        if width == 0 || height == 0 {
+            return vec![];
+        }
+
+        let ratio = (DETECTION_SIZE / f64::max(width as f64, height as f64)).min(1.0);
+        let gray = Integral::new(&grayscale(img, ratio));
+
+        // Features are compared to thresholds relative to the contrast of
+        // the window without its outermost pixels
+        let inner = Feature {
+            rects: vec![WeightedRect {
+                x: 1,
+                y: 1,
+                width: self.window.0 - 2,
+                height: self.window.1 - 2,
+                weight: 1.0,
+            }],
+        };
+
+        let mut candidates = vec![];
+        let mut scale = 1.0;
+        loop {
+            let window_width = (self.window.0 as f64 * scale).round() as u32;
+            let window_height = (self.window.1 as f64 * scale).round() as u32;
+            if window_width > gray.width || window_height > gray.height {
+                break;
+            }
+
+            let features: Vec<Feature> = (self.features.iter())
+                .map(|f| f.scale(scale, (window_width, window_height)))
+                .collect();
+            let inner = inner
+                .scale(scale, (window_width, window_height))
+                .rects
+                .remove(0);
+            let step = (scale * 1.5).round().max(1.0) as usize;
+            for y in (0..=gray.height - window_height).step_by(step) {
+                for x in (0..=gray.width - window_width).step_by(step) {
+                    if self.accepts(&gray, &features, &inner, x, y) {
+                        candidates.push(Crop {
+                            x,
+                            y,
+                            width: window_width,
+                            height: window_height,
+                        });
+                    }
+                }
+            }
+            scale *= self.scale_factor.max(1.01);
+        }
+
+        let mut groups = group(&candidates, self.min_neighbors);
+        groups.sort_by_key(|g| std::cmp::Reverse(g.1));
+        groups
+            .into_iter()
+            .map(|(crop, neighbors)| (scale_back(&crop, ratio, width, height), neighbors))
+            .collect()
+    }
+
+    // `features` and the `inner` rectangle normalizing them are scaled to
+    // the size of the window at `x`, `y`
+    fn accepts(
+        &self,
+        gray: &Integral,
+        features: &[Feature],
+        inner: &WeightedRect,
+        x: u32,
+        y: u32,
+    ) -> bool {
+        let inner = WeightedRect {
+            x: x + inner.x,
+            y: y + inner.y,
+            ..*inner
+        };
+        let area = (inner.width * inner.height) as f64;
+        let sum = gray.sum(&gray.values, &inner);
+        let squares = gray.sum(&gray.squares, &inner);
+        let variance = area * squares - sum * sum;
+        let norm = if variance > 0.0 { variance.sqrt() } else { 1.0 };
+
+        let value = |feature: &Feature| {
+            feature
+                .rects
+                .iter()
+                .map(|r| {
+                    let r = WeightedRect {
+                        x: x + r.x,
+                        y: y + r.y,
+                        ..*r
+                    };
+                    r.weight * gray.sum(&gray.values, &r)
+                })
+                .sum::<f64>()
+                / norm
+        };
+
+        self.stages.iter().all(|stage| {
+            let sum: f64 = stage
+                .classifiers
+                .iter()
+                .map(|classifier| {
+                    let mut index = 0;
+                    loop {
+                        let node = &classifier.nodes[index as usize];
+                        index = if value(&features[node.feature]) < node.threshold {
+                            node.left
+                        } else {
+                            node.right
+                        };
+                        if index <= 0 {
+                            break classifier.leaves[(-index) as usize];
+                        }
+                    }
+                })
+                .sum();
+            sum >= stage.threshold
+        })
+    }
+}
+
+impl RegionDetector for HaarCascade {
+    /// Confidence grows with the overlapping raw detections, it is 0.5 with
+    /// `min_neighbors` of them.
+    fn detect(&self, img: &dyn Image) -> Vec<(Crop, f64)> {
+        self.detect_objects(img)
+            .into_iter()
+            .map(|(crop, neighbors)| {
+                let confidence = neighbors as f64 / (neighbors + self.min_neighbors.max(1)) as f64;
+                (crop, confidence)
+            })
+            .collect()
+    }
+}
+
+impl Feature {
+    // Rectangles for a `window` sized window `scale` times the trained size.
+    // Both edges are rounded, so rectangles stay inside the window.
+    fn scale(&self, scale: f64, window: (u32, u32)) -> Feature {
+        let span = |start: u32, size: u32, limit: u32| {
+            let end = (((start + size) as f64 * scale).round() as u32).clamp(1, limit);
+            let start = ((start as f64 * scale).round() as u32).min(end - 1);
+            (start, end - start)
+        };
+        let mut rects: Vec<WeightedRect> = self
+            .rects
+            .iter()
+            .map(|r| {
+                let (x, width) = span(r.x, r.width, window.0);
+                let (y, height) = span(r.y, r.height, window.1);
+                WeightedRect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    weight: r.weight,
+                }
+            })
+            .collect();
+
+        // Rounding changes the areas, keep features responding with 0 to flat
+        // windows when they did so unscaled
+        let weighted_area =
+            |rects: &[WeightedRect]| -> f64 { rects.iter().map(|r| r.weight * r.area()).sum() };
+        let balanced = weighted_area(&self.rects).abs() < 1e-6;
+        if balanced && rects.len() > 1 {
+            rects[0].weight = -weighted_area(&rects[1..]) / rects[0].area();
+        }
+        Feature { rects }
+    }
+}
+
+impl WeightedRect {
+    fn area(&self) -> f64 {
+        (self.width * self.height) as f64
+    }
+}
+
+struct Integral {
+    width: u32,
+    height: u32,
+    values: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl Integral {
+    fn new(gray: &(Vec<f64>, u32, u32)) -> Integral {
+        let (ref pixels, width, height) = *gray;
+        let stride = width as usize + 1;
+        let mut values = vec![0.0; stride * (height as usize + 1)];
+        let mut squares = values.clone();
+        for y in 0..height as usize {
+            let (mut row, mut row_squares) = (0.0, 0.0);
+            for x in 0..width as usize {
+                let p = pixels[y * width as usize + x];
+                row += p;
+                row_squares += p * p;
+                let i = (y + 1) * stride + x + 1;
+                values[i] = values[i - stride] + row;
+                squares[i] = squares[i - stride] + row_squares;
+            }
+        }
+
+        Integral {
+            width,
+            height,
+            values,
+            squares,
+        }
+    }
+
+    fn sum(&self, table: &[f64], r: &WeightedRect) -> f64 {
+        let stride = self.width as usize + 1;
+        let (x, y) = (r.x as usize, r.y as usize);
+        let (right, bottom) = (x + r.width as usize, y + r.height as usize);
+        table[bottom * stride + right] - table[y * stride + right] - table[bottom * stride + x]
+            + table[y * stride + x]
+    }
+}
+
+// Area-averaged luma of the image scaled by `ratio`
+fn grayscale(img: &dyn Image, ratio: f64) -> (Vec<f64>, u32, u32) {
+    let (width, height) = (img.width(), img.height());
+    let gray_width = ((width as f64 * ratio).round() as u32).max(1);
+    let gray_height = ((height as f64 * ratio).round() as u32).max(1);
+
+    let mut pixels = Vec::with_capacity((gray_width * gray_height) as usize);
+    for gy in 0..gray_height {
+        let top = gy * height / gray_height;
+        let bottom = ((gy + 1) * height / gray_height).max(top + 1);
+        for gx in 0..gray_width {
+            let left = gx * width / gray_width;
+            let right = ((gx + 1) * width / gray_width).max(left + 1);
+            let mut sum = 0.0;
+            for y in top..bottom {
+                for x in left..right {
+                    let p = img.get(x, y);
+                    sum += 0.299 * p.r as f64 + 0.587 * p.g as f64 + 0.114 * p.b as f64;
+                }
+            }
+            pixels.push(sum / ((right - left) * (bottom - top)) as f64);
+        }
+    }
+
+    (pixels, gray_width, gray_height)
+}
+
+fn scale_back(crop: &Crop, ratio: f64, width: u32, height: u32) -> Crop {
+    let x = ((crop.x as f64 / ratio).round() as u32).min(width - 1);
+    let y = ((crop.y as f64 / ratio).round() as u32).min(height - 1);
+    Crop {
+        x,
+        y,
+        width: ((crop.width as f64 / ratio).round() as u32).clamp(1, width - x),
+        height: ((crop.height as f64 / ratio).round() as u32).clamp(1, height - y),
+    }
+}
+
+// Averages of clusters of similar windows with more than `min_neighbors`
+// members, leaving out clusters inside clearly stronger ones, as
+// `cv::groupRectangles` does
+fn group(candidates: &[Crop], min_neighbors: usize) -> Vec<(Crop, usize)> {
+    if min_neighbors == 0 {
+        return candidates.iter().map(|c| (c.clone(), 1)).collect();
+    }
+
+    let similar = |a: &Crop, b: &Crop| {
+        let delta = 0.2 * 0.5 * (a.width.min(b.width) + a.height.min(b.height)) as f64;
+        (a.x as f64 - b.x as f64).abs() <= delta
+            && (a.y as f64 - b.y as f64).abs() <= delta
+            && ((a.x + a.width) as f64 - (b.x + b.width) as f64).abs() <= delta
+            && ((a.y + a.height) as f64 - (b.y + b.height) as f64).abs() <= delta
+    };
+
+    let mut labels: Vec<usize> = (0..candidates.len()).collect();
+    fn root(labels: &mut [usize], mut i: usize) -> usize {
+        while labels[i] != i {
+            labels[i] = labels[labels[i]];
+            i = labels[i];
+        }
+        i
+    }
+    for i in 0..candidates.len() {
+        for j in 0..i {
+            if similar(&candidates[i], &candidates[j]) {
+                let (a, b) = (root(&mut labels, i), root(&mut labels, j));
+                labels[a] = b;
+            }
+        }
+    }
+
+    let mut clusters: Vec<(usize, [u64; 4], usize)> = vec![];
+    for (i, c) in candidates.iter().enumerate() {
+        let label = root(&mut labels, i);
+        let cluster = match clusters.iter().position(|cl| cl.0 == label) {
+            Some(index) => &mut clusters[index],
+            None => {
+                clusters.push((label, [0; 4], 0));
+                clusters.last_mut().unwrap()
+            }
+        };
+        for (sum, value) in cluster.1.iter_mut().zip(&[c.x, c.y, c.width, c.height]) {
+            *sum += *value as u64;
+        }
+        cluster.2 += 1;
+    }
+
+    let groups: Vec<(Crop, usize)> = clusters
+        .into_iter()
+        .filter(|cl| cl.2 > min_neighbors)
+        .map(|(_, sums, count)| {
+            let mean = |sum: u64| (sum as f64 / count as f64).round() as u32;
+            let crop = Crop {
+                x: mean(sums[0]),
+                y: mean(sums[1]),
+                width: mean(sums[2]),
+                height: mean(sums[3]),
+            };
+            (crop, count)
+        })
+        .collect();
+
+    let inside = |(a, n): &(Crop, usize), (b, m): &(Crop, usize)| {
+        let dx = (0.2 * b.width as f64).round() as i64;
+        let dy = (0.2 * b.height as f64).round() as i64;
+        let (ax, ay, bx, by) = (a.x as i64, a.y as i64, b.x as i64, b.y as i64);
+        ax >= bx - dx
+            && ay >= by - dy
+            && ax + a.width as i64 <= bx + b.width as i64 + dx
+            && ay + a.height as i64 <= by + b.height as i64 + dy
+            && (*m > (*n).max(3) || *n < 3)
+    };
+    groups
+        .iter()
+        .enumerate()
+        .filter(|&(i, g)| !(groups.iter().enumerate()).any(|(j, other)| i != j && inside(g, other)))
+        .map(|(_, g)| g.clone())
+        .collect()
+}
+
+fn parse_cascade(xml: &str) -> Result<HaarCascade, String> {
+    let root = Element::parse(xml)?;
+    let storage = root.child("opencv_storage").ok_or("No opencv_storage")?;
+    let cascade = storage
+        .children
+        .iter()
+        .find(|c| c.child("stages").is_some())
+        .ok_or("No cascade with stages found")?;
+
+    if cascade.child("stageType").map(|s| s.text.trim()) != Some("BOOST") {
+        return Err("Only boosted cascades are supported".to_string());
+    }
+    if cascade.child("featureType").map(|s| s.text.trim()) != Some("HAAR") {
+        return Err("Only Haar features are supported".to_string());
+    }
+    let window = (
+        cascade.number::<u32>("width")?,
+        cascade.number::<u32>("height")?,
+    );
+    if window.0 < 3 || window.1 < 3 {
+        return Err("Window of the cascade is too small".to_string());
+    }
+
+    let features = cascade
+        .items("features")?
+        .map(|feature| {
+            if let Some(tilted) = feature.child("tilted") {
+                if tilted.text.trim() != "0" {
+                    return Err("Tilted features are not supported".to_string());
+                }
+            }
+            let rects = feature
+                .items("rects")?
+                .map(|rect| {
+                    let n = numbers(&rect.text)?;
+                    if n.len() != 5 || n[..4].iter().any(|&v| v < 0.0) {
+                        return Err(format!("Invalid rectangle '{}'", rect.text.trim()));
+                    }
+                    if n[0] + n[2] > window.0 as f64 || n[1] + n[3] > window.1 as f64 {
+                        return Err("Rectangle outside of the window".to_string());
+                    }
+                    Ok(WeightedRect {
+                        x: n[0] as u32,
+                        y: n[1] as u32,
+                        width: n[2] as u32,
+                        height: n[3] as u32,
+                        weight: n[4],
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(Feature { rects })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let stages = cascade
+        .items("stages")?
+        .map(|stage| {
+            let classifiers = stage
+                .items("weakClassifiers")?
+                .map(|weak| parse_classifier(weak, features.len()))
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(Stage {
+                threshold: stage.number("stageThreshold")?,
+                classifiers,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(HaarCascade {
+        scale_factor: 1.1,
+        min_neighbors: 3,
+        window,
+        stages,
+        features,
+    })
+}
+
+fn parse_classifier(weak: &Element, feature_count: usize) -> Result<Classifier, String> {
+    let internal = numbers(&weak.child("internalNodes").ok_or("No internalNodes")?.text)?;
+    let leaves = numbers(&weak.child("leafValues").ok_or("No leafValues")?.text)?;
+    if internal.is_empty() || internal.len() % 4 != 0 {
+        return Err("Nodes need 4 values each".to_string());
+    }
+
+    let nodes: Vec<Node> = internal
+        .chunks(4)
+        .map(|n| Node {
+            left: n[0] as i32,
+            right: n[1] as i32,
+            feature: n[2] as usize,
+            threshold: n[3],
+        })
+        .collect();
+    // Children come after their parent, so evaluating a tree always ends
+    // in a leaf
+    let valid = |parent: usize, index: i32| {
+        if index > 0 {
+            parent < index as usize && (index as usize) < nodes.len()
+        } else {
+            ((-index) as usize) < leaves.len()
+        }
+    };
+    for (i, node) in nodes.iter().enumerate() {
+        if node.feature >= feature_count || !valid(i, node.left) || !valid(i, node.right) {
+            return Err("Node references a missing feature, node or leaf".to_string());
+        }
+    }
+    Ok(Classifier { nodes, leaves })
+}
+
+fn numbers(text: &str) -> Result<Vec<f64>, String> {
+    text.split_whitespace()
+        .map(|n| n.parse().map_err(|_| format!("Invalid number '{}'", n)))
+        .collect()
+}
+
+// Just enough XML for OpenCV storage files: elements and text, attributes
+// are skipped
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    // Document root wrapping the top level elements
+    fn parse(xml: &str) -> Result<Element, String> {
+        let mut stack = vec![Element::default()];
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            stack.last_mut().unwrap().text.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let skip_to = |end: &str| rest.find(end).map(|i| i + end.len());
+            let consumed = if rest.starts_with("<!--") {
+                skip_to("-->")
+            } else if rest.starts_with("<?") {
+                skip_to("?>")
+            } else if rest.starts_with("<!") {
+                skip_to(">")
+            } else if let Some(tag) = rest.strip_prefix("</") {
+                let end = tag.find('>').ok_or("Unterminated closing tag")?;
+                let name = tag[..end].trim();
+                let element = stack.pop().filter(|_| !stack.is_empty());
+                match element {
+                    Some(element) if element.name == name => {
+                        stack.last_mut().unwrap().children.push(element)
+                    }
+                    _ => return Err(format!("Unexpected closing tag '{}'", name)),
+                }
+                Some(end + 3)
+            } else {
+                let end = tag_end(rest).ok_or("Unterminated tag")?;
+                let tag = &rest[1..end];
+                let self_closing = tag.ends_with('/');
+                let name = tag
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or("");
+                if name.is_empty() {
+                    return Err("Tag without a name".to_string());
+                }
+                let element = Element {
+                    name: name.to_string(),
+                    ..Element::default()
+                };
+                if self_closing {
+                    stack.last_mut().unwrap().children.push(element);
+                } else {
+                    stack.push(element);
+                }
+                Some(end + 1)
+            };
+            rest = &rest[consumed.ok_or("Unterminated markup")?..];
+        }
+
+        match stack.pop() {
+            Some(root) if stack.is_empty() => Ok(root),
+            _ => Err("Unclosed elements".to_string()),
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    // Entries of a list element, named `_` by OpenCV
+    fn items(&self, name: &str) -> Result<impl Iterator<Item = &Element>, String> {
+        let list = self.child(name).ok_or(format!("No {}", name))?;
+        Ok(list.children.iter().filter(|c| c.name == "_"))
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<T, String> {
+        let text = &self.child(name).ok_or(format!("No {}", name))?.text;
+        text.trim()
+            .parse()
+            .map_err(|_| format!("Invalid {} '{}'", name, text.trim()))
+    }
+}
+
+// Index of the `>` ending the tag at the start, skipping quoted attributes
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RgbBuffer, RGB};
+    use super::*;
+
+    // One stage with a single stump: the left half of the 6x6 window darker
+    // than the right one
+    const CASCADE: &str = r#"<?xml version="1.0"?>
+<opencv_storage>
+<cascade type_id="opencv-cascade-classifier"><stageType>BOOST</stageType>
+  <featureType>HAAR</featureType>
+  <height>6</height>
+  <width>6</width>
+  <stageParams>
+    <maxWeakCount>1</maxWeakCount></stageParams>
+  <featureParams>
+    <maxCatCount>0</maxCatCount></featureParams>
+  <stageNum>1</stageNum>
+  <stages>
+    <!-- stage 0 -->
+    <_>
+      <maxWeakCount>1</maxWeakCount>
+      <stageThreshold>0.5</stageThreshold>
+      <weakClassifiers>
+        <_>
+          <internalNodes>
+            0 -1 0 0.3</internalNodes>
+          <leafValues>
+            -1. 1.</leafValues></_></weakClassifiers></_></stages>
+  <features>
+    <_>
+      <rects>
+        <_>
+          0 0 6 6 -1.</_>
+        <_>
+          3 0 3 6 2.</_></rects></_></features></cascade>
+</opencv_storage>
+"#;
+
+    // The first three weak classifiers of the first stage and their
+    // features from OpenCV's haarcascade_frontalface_default.xml
+    const FRONTALFACE_SUBSET: &str = r#"<?xml version="1.0"?>
+<!--
+    Stump-based 24x24 discrete(?) adaboost frontal face detector.
+    Created by Rainer Lienhart.
+-->
+<opencv_storage>
+<cascade type_id="opencv-cascade-classifier"><stageType>BOOST</stageType>
+  <featureType>HAAR</featureType>
+  <height>24</height>
+  <width>24</width>
+  <stageParams>
+    <maxWeakCount>211</maxWeakCount></stageParams>
+  <featureParams>
+    <maxCatCount>0</maxCatCount></featureParams>
+  <stageNum>1</stageNum>
+  <stages>
+    <_>
+      <maxWeakCount>3</maxWeakCount>
+      <stageThreshold>-5.0425500869750977e+00</stageThreshold>
+      <weakClassifiers>
+        <_>
+          <internalNodes>
+            0 -1 0 -3.1511999666690826e-02</internalNodes>
+          <leafValues>
+            2.0875380039215088e+00 -2.2172100543975830e+00</leafValues></_>
+        <_>
+          <internalNodes>
+            0 -1 1 1.2396000325679779e-02</internalNodes>
+          <leafValues>
+            -1.8633940219879150e+00 1.3272049427032471e+00</leafValues></_>
+        <_>
+          <internalNodes>
+            0 -1 2 2.1927999332547188e-02</internalNodes>
+          <leafValues>
+            -1.5105249881744385e+00 1.0625729560852051e+00</leafValues></_></weakClassifiers></_></stages>
+  <features>
+    <_>
+      <rects>
+        <_>
+          6 4 12 9 -1.</_>
+        <_>
+          6 7 12 3 3.</_></rects></_>
+    <_>
+      <rects>
+        <_>
+          6 4 12 7 -1.</_>
+        <_>
+          10 4 4 7 3.</_></rects></_>
+    <_>
+      <rects>
+        <_>
+          3 9 18 9 -1.</_>
+        <_>
+          3 12 18 3 3.</_></rects></_></features></cascade>
+</opencv_storage>
+"#;
+
+    // Gray image with a face drawn on a 24x24 grid scaled by `cell` at
+    // `origin`: dark eyes with a bright bridge between them and bright
+    // cheeks below
+    fn face(size: u32, origin: u32, cell: u32) -> RgbBuffer<'static> {
+        RgbBuffer::from_fn(size, size, |x, y| {
+            let inside = (origin..origin + 24 * cell).contains(&x)
+                && (origin..origin + 24 * cell).contains(&y);
+            let (gx, gy) = (
+                (x.max(origin) - origin) / cell,
+                (y.max(origin) - origin) / cell,
+            );
+            let v = if !inside {
+                128
+            } else if (7..10).contains(&gy) && (4..20).contains(&gx) {
+                if (10..14).contains(&gx) {
+                    200
+                } else {
+                    40
+                }
+            } else if (12..15).contains(&gy) && (3..21).contains(&gx) {
+                200
+            } else {
+                128
+            };
+            RGB::new(v, v, v)
+        })
+    }
+
+    fn image(width: u32, height: u32, square: Crop) -> RgbBuffer<'static> {
+        RgbBuffer::from_fn(width, height, |x, y| {
+            let inside = x >= square.x
+                && x < square.x + square.width
+                && y >= square.y
+                && y < square.y + square.height;
+            let v = if !inside {
+                128
+            } else if x < square.x + square.width / 2 {
+                20
+            } else {
+                235
+            };
+            RGB::new(v, v, v)
+        })
+    }
+
+    #[test]
+    fn parses_traincascade_xml() {
+        let cascade = HaarCascade::from_xml(CASCADE).unwrap();
+
+        assert_eq!(cascade.window, (6, 6));
+        assert_eq!(cascade.stages.len(), 1);
+        assert_eq!(cascade.stages[0].classifiers[0].leaves, vec![-1.0, 1.0]);
+        assert_eq!(cascade.features[0].rects.len(), 2);
+        assert_eq!(cascade.features[0].rects[1].weight, 2.0);
+    }
+
+    #[test]
+    fn rejects_unsupported_and_broken_cascades() {
+        let lbp = CASCADE.replace(">HAAR<", ">LBP<");
+        let tilted = CASCADE.replace("</rects>", "</rects><tilted>1</tilted>");
+        let missing_feature = CASCADE.replace("0 -1 0 0.3", "0 -1 1 0.3");
+
+        for xml in &[&lbp, &tilted, &missing_feature, &CASCADE[..300]] {
+            assert!(matches!(
+                HaarCascade::from_xml(xml),
+                Err(Error::InvalidCascade(_))
+            ));
+        }
+        assert!(matches!(
+            HaarCascade::open("/nonexistent/cascade.xml"),
+            Err(Error::InvalidCascade(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_cyclic_trees() {
+        let self_loop = CASCADE.replace("0 -1 0 0.3", "1 -1 0 0.3 1 -1 0 0.3");
+        let back_edge = CASCADE.replace("0 -1 0 0.3", "1 -1 0 0.3 2 -1 0 0.3 1 -1 0 0.3");
+        let forward = CASCADE.replace("0 -1 0 0.3", "1 -1 0 0.3 0 -1 0 0.3");
+
+        for xml in &[&self_loop, &back_edge] {
+            assert!(matches!(
+                HaarCascade::from_xml(xml),
+                Err(Error::InvalidCascade(_))
+            ));
+        }
+        assert!(HaarCascade::from_xml(&forward).is_ok());
+    }
+
+    #[test]
+    fn detects_the_pattern_at_several_scales() {
+        let cascade = HaarCascade::from_xml(CASCADE).unwrap();
+        let square = Crop {
+            x: 50,
+            y: 30,
+            width: 24,
+            height: 24,
+        };
+
+        let detections = cascade.detect(&image(120, 90, square.clone()));
+
+        // Windows of several sizes straddle the dark to bright step, the
+        // best supported one is centred on it
+        assert!(detections
+            .iter()
+            .all(|(found, _)| found.intersects(&square)));
+        let (found, confidence) = &detections[0];
+        let center = |c: &Crop| (c.x + c.width / 2, c.y + c.height / 2);
+        let (x, y) = center(found);
+        assert!((59..=65).contains(&x) && (39..=45).contains(&y));
+        assert!(found.width >= 6 && found.width <= 24);
+        assert!(*confidence > 0.5 && *confidence < 1.0);
+        assert!(detections[1..]
+            .iter()
+            .all(|d| d.1 < 1.0 && &d.1 <= confidence));
+        assert!(cascade
+            .detect(&image(120, 90, Crop { width: 0, ..square }))
+            .is_empty());
+    }
+
+    #[test]
+    fn frontalface_subset_finds_a_drawn_face() {
+        let cascade = HaarCascade::from_xml(FRONTALFACE_SUBSET).unwrap();
+        assert_eq!(cascade.window, (24, 24));
+        let drawn = Crop {
+            x: 24,
+            y: 24,
+            width: 48,
+            height: 48,
+        };
+
+        let faces = cascade.detect_objects(&face(96, 24, 2));
+
+        assert!(!faces.is_empty());
+        assert!(
+            faces[0].0.intersection_area(&drawn) * 2 > drawn.width as u64 * drawn.height as u64
+        );
+        assert!(faces[0].1 > cascade.min_neighbors);
+        // Flat windows fail all three classifiers
+        let flat = Crop { width: 0, ..drawn };
+        assert!(cascade.detect_objects(&image(96, 96, flat)).is_empty());
+    }
+
+    #[test]
+    fn scaled_features_stay_inside_the_window() {
+        let mut cascade = HaarCascade::from_xml(CASCADE).unwrap();
+        let nothing = Crop {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+        let uniform = |size| image(size, size, nothing.clone());
+
+        for &scale_factor in &[1.05, 1.1, 1.2, 1.25, 1.3, 1.5] {
+            cascade.scale_factor = scale_factor;
+            for size in (7..=79).step_by(2) {
+                assert!(cascade.detect_objects(&uniform(size)).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn groups_overlapping_windows() {
+        let window = |x, y, size| Crop {
+            x,
+            y,
+            width: size,
+            height: size,
+        };
+        let candidates = [
+            window(10, 10, 20),
+            window(11, 10, 20),
+            window(10, 12, 22),
+            window(60, 60, 20),
+        ];
+
+        let groups = group(&candidates, 2);
+
+        assert_eq!(groups, vec![(window(10, 11, 21), 3)]);
+        // As in OpenCV, more than `min_neighbors` windows are needed
+        assert!(group(&candidates, 3).is_empty());
+        assert_eq!(group(&candidates, 0).len(), 4);
+    }
+
+    #[test]
+    fn drops_groups_inside_stronger_ones() {
+        let window = |x, y, size| Crop {
+            x,
+            y,
+            width: size,
+            height: size,
+        };
+        let mut candidates = vec![window(10, 10, 40); 6];
+        candidates.extend(vec![window(20, 20, 10); 2]);
+        candidates.extend(vec![window(100, 100, 10); 2]);
+
+        let groups = group(&candidates, 1);
+
+        assert_eq!(
+            groups,
+            vec![(window(10, 10, 40), 6), (window(100, 100, 10), 2)]
+        );
+    }
+}
@@ -1,10 +1,21 @@
 extern crate image as image_ext;
 
-use self::image_ext::{imageops, FilterType, GenericImage, ImageBuffer, Pixel};
+use self::image_ext::{
+    imageops, ColorType, DynamicImage, FilterType, GenericImage, ImageBuffer, ImageOutputFormat,
+    Pixel,
+};
+use super::exif::{read_orientation, Orientation};
+use super::math::bounds;
+use super::resize::area_average;
+use super::Analyzer;
+use super::CropSettings;
+use super::Error;
 use super::Image;
 use super::ResizableImage;
+use super::ResizeFilter;
 use super::RGB;
-use std;
+use std::num::NonZeroU32;
+use std::path::Path;
 
 impl<I, P> Image for I
 where
@@ -37,4 +48,300 @@ where
     fn resize(&self, width: u32, height: u32) -> ImageBuffer<P, std::vec::Vec<u8>> {
         imageops::resize(self, width, height, FilterType::Lanczos3)
     }
+
+    fn resize_with_filter(
+        &self,
+        width: u32,
+        height: u32,
+        filter: ResizeFilter,
+    ) -> ImageBuffer<P, std::vec::Vec<u8>> {
+        let filter = match filter {
+            ResizeFilter::Box => return box_resize(self, width, height),
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        };
+
+        imageops::resize(self, width, height, filter)
+    }
+}
+
+fn box_resize<I, P>(img: &I, width: u32, height: u32) -> ImageBuffer<P, Vec<u8>>
+where
+    I: GenericImage<Pixel = P> + 'static,
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    let mut output = ImageBuffer::new(width, height);
+    let channel_count = P::channel_count() as usize;
+
+    area_average(
+        GenericImage::width(img),
+        GenericImage::height(img),
+        width,
+        height,
+        |x, y| {
+            let mut color = [0.0; 4];
+            for (c, &s) in color.iter_mut().zip(img.get_pixel(x, y).channels()) {
+                *c = s as f64;
+            }
+            color
+        },
+        |x, y, color| {
+            let mut channels = [0u8; 4];
+            for (c, &s) in channels.iter_mut().zip(color.iter()) {
+                *c = bounds(s);
+            }
+            output.put_pixel(x, y, *P::from_slice(&channels[..channel_count]));
+        },
+    );
+
+    output
+}
+
+/// Finds the best crop and scales it to exactly `width` × `height`
+/// using `settings.resize_filter`.
+pub fn thumbnail<I, P>(
+    img: &I,
+    width: NonZeroU32,
+    height: NonZeroU32,
+    settings: &CropSettings,
+) -> Result<ImageBuffer<P, Vec<u8>>, Error>
+where
+    I: GenericImage<Pixel = P> + 'static,
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    let analyzer = Analyzer::new(settings.clone());
+    let crop = analyzer.find_best_crop(img, width, height)?.crop;
+
+    let cropped = ImageBuffer::from_fn(crop.width, crop.height, |x, y| {
+        img.get_pixel(crop.x + x, crop.y + y)
+    });
+
+    Ok(cropped.resize_with_filter(width.get(), height.get(), settings.resize_filter))
+}
+
+/// Same as `thumbnail`, but returns the result encoded in the given format.
+pub fn thumbnail_encoded<I, P, F>(
+    img: &I,
+    width: NonZeroU32,
+    height: NonZeroU32,
+    settings: &CropSettings,
+    format: F,
+) -> Result<Vec<u8>, Error>
+where
+    I: GenericImage<Pixel = P> + 'static,
+    P: Pixel<Subpixel = u8> + 'static,
+    F: Into<ImageOutputFormat>,
+{
+    let thumbnail = thumbnail(img, width, height, settings)?;
+
+    let mut encoded = Vec::new();
+    to_dynamic_image(&thumbnail)
+        .write_to(&mut encoded, format)
+        .map_err(|e| Error::EncodingFailed(e.to_string()))?;
+
+    Ok(encoded)
+}
+
+/// Decodes an image and turns it upright according to its EXIF orientation.
+///
+/// The returned orientation maps crops of the upright image back to the
+/// stored raster with `Orientation::crop_to_stored`.
+pub fn load_oriented(data: &[u8]) -> Result<(DynamicImage, Orientation), Error> {
+    let img =
+        image_ext::load_from_memory(data).map_err(|e| Error::DecodingFailed(e.to_string()))?;
+    let orientation = read_orientation(data).unwrap_or_default();
+
+    Ok((apply_orientation(&img, orientation), orientation))
+}
+
+/// Same as `load_oriented`, but reads the image from a file.
+pub fn open_oriented<P: AsRef<Path>>(path: P) -> Result<(DynamicImage, Orientation), Error> {
+    let data = std::fs::read(path).map_err(|e| Error::DecodingFailed(e.to_string()))?;
+    load_oriented(&data)
+}
+
+/// Transforms the stored raster into the image as it should be displayed.
+pub fn apply_orientation(img: &DynamicImage, orientation: Orientation) -> DynamicImage {
+    match orientation {
+        Orientation::Normal => img.clone(),
+        Orientation::FlipHorizontal => img.fliph(),
+        Orientation::Rotate180 => img.rotate180(),
+        Orientation::FlipVertical => img.flipv(),
+        Orientation::Transpose => img.rotate90().fliph(),
+        Orientation::Rotate90 => img.rotate90(),
+        Orientation::Transverse => img.rotate90().flipv(),
+        Orientation::Rotate270 => img.rotate270(),
+    }
+}
+
+fn to_dynamic_image<P>(buffer: &ImageBuffer<P, Vec<u8>>) -> DynamicImage
+where
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    let (width, height) = buffer.dimensions();
+
+    match P::color_type() {
+        ColorType::Gray(8) => {
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| {
+                buffer.get_pixel(x, y).to_luma()
+            }))
+        }
+        ColorType::GrayA(8) => {
+            DynamicImage::ImageLumaA8(ImageBuffer::from_fn(width, height, |x, y| {
+                buffer.get_pixel(x, y).to_luma_alpha()
+            }))
+        }
+        ColorType::RGB(8) => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_fn(width, height, |x, y| {
+                buffer.get_pixel(x, y).to_rgb()
+            }))
+        }
+        _ => DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| {
+            buffer.get_pixel(x, y).to_rgba()
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::image_ext::{ImageFormat, Rgb, RgbImage};
+    use super::*;
+
+    fn non_zero(value: u32) -> NonZeroU32 {
+        NonZeroU32::new(value).unwrap()
+    }
+
+    fn test_image() -> RgbImage {
+        ImageBuffer::from_fn(120, 60, |x, _| {
+            if x > 80 {
+                Rgb([255, 200, 159])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn thumbnail_has_exactly_requested_dimensions() {
+        let settings = CropSettings::default();
+
+        let result = thumbnail(&test_image(), non_zero(32), non_zero(24), &settings).unwrap();
+
+        assert_eq!(result.dimensions(), (32, 24));
+    }
+
+    #[test]
+    fn thumbnail_uses_configured_filter() {
+        let settings = CropSettings {
+            resize_filter: ResizeFilter::Nearest,
+            ..CropSettings::default()
+        };
+
+        let result = thumbnail(&test_image(), non_zero(10), non_zero(10), &settings).unwrap();
+
+        assert_eq!(result.dimensions(), (10, 10));
+        assert!(result
+            .pixels()
+            .all(|p| *p == Rgb([255, 200, 159]) || *p == Rgb([255, 255, 255])));
+    }
+
+    #[test]
+    fn box_resize_keeps_pixel_type() {
+        let resized = test_image().resize_with_filter(30, 15, ResizeFilter::Box);
+
+        assert_eq!(resized.dimensions(), (30, 15));
+        assert_eq!(*resized.get_pixel(0, 0), Rgb([255, 255, 255]));
+        assert_eq!(*resized.get_pixel(29, 14), Rgb([255, 200, 159]));
+    }
+
+    #[test]
+    fn box_prescale_gives_same_crop_as_lanczos() {
+        // Two faces of different size on a textured background, large enough
+        // to be prescaled.
+        let image: RgbImage = ImageBuffer::from_fn(1200, 800, |x, y| {
+            let in_circle =
+                |cx: i64, cy: i64, r: i64| (x as i64 - cx).pow(2) + (y as i64 - cy).pow(2) < r * r;
+            if in_circle(850, 300, 120) || in_circle(300, 550, 60) {
+                Rgb([234, 171, 132])
+            } else if (x / 40 + y / 40) % 2 == 1 {
+                Rgb([90, 60, 60])
+            } else {
+                Rgb([60, 90, 60])
+            }
+        });
+
+        let crop_with = |filter| {
+            let settings = CropSettings {
+                prescale_filter: filter,
+                ..CropSettings::default()
+            };
+            Analyzer::new(settings)
+                .find_best_crop(&image, non_zero(300), non_zero(400))
+                .unwrap()
+                .crop
+        };
+
+        let lanczos = crop_with(ResizeFilter::Lanczos3);
+        let boxed = crop_with(ResizeFilter::Box);
+
+        assert_eq!((boxed.width, boxed.height), (lanczos.width, lanczos.height));
+        // One step of the candidate grid in the original image coordinates
+        let tolerance = 8 * 2;
+        assert!((boxed.x as i64 - lanczos.x as i64).abs() <= tolerance);
+        assert!((boxed.y as i64 - lanczos.y as i64).abs() <= tolerance);
+    }
+
+    #[test]
+    fn orientation_round_trips_crops() {
+        let stored: RgbImage =
+            ImageBuffer::from_fn(7, 5, |x, y| Rgb([x as u8 * 30, y as u8 * 50, 0]));
+        let stored = DynamicImage::ImageRgb8(stored);
+        let crop = super::super::Crop {
+            x: 1,
+            y: 2,
+            width: 2,
+            height: 3,
+        };
+
+        for value in 1..=8 {
+            let orientation = Orientation::from_exif(value).unwrap();
+            let upright = apply_orientation(&stored, orientation);
+            let (w, h) = GenericImage::dimensions(&upright);
+            assert_eq!(orientation.swaps_dimensions(), (w, h) == (5, 7));
+
+            let mapped = orientation.crop_to_stored(&crop, 7, 5);
+            let mut expected: Vec<_> = (0..crop.height)
+                .flat_map(|y| (0..crop.width).map(move |x| (x, y)))
+                .map(|(x, y)| upright.get_pixel(crop.x + x, crop.y + y))
+                .collect();
+            let mut actual: Vec<_> = (0..mapped.height)
+                .flat_map(|y| (0..mapped.width).map(move |x| (x, y)))
+                .map(|(x, y)| stored.get_pixel(mapped.x + x, mapped.y + y))
+                .collect();
+            expected.sort_by_key(|p| p.data);
+            actual.sort_by_key(|p| p.data);
+            assert_eq!(expected, actual, "orientation {:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn thumbnail_encoded_produces_png() {
+        let settings = CropSettings::default();
+
+        let encoded = thumbnail_encoded(
+            &test_image(),
+            non_zero(16),
+            non_zero(16),
+            &settings,
+            ImageFormat::PNG,
+        )
+        .unwrap();
+
+        assert_eq!(&encoded[..8], b"\x89PNG\r\n\x1a\n");
+        let decoded = image_ext::load_from_memory(&encoded).unwrap();
+        assert_eq!(GenericImage::dimensions(&decoded), (16, 16));
+    }
 }
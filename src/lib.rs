@@ -6,13 +6,50 @@ extern crate rand;
 #[macro_use]
 extern crate proptest;
 
+mod border;
+mod buffer;
+mod edge;
+mod exif;
+mod haar;
 mod math;
-
+mod regions;
+mod resize;
+mod text;
+mod video;
+mod yuv;
+
+pub use self::border::{detect_content_bounds, BORDER_TOLERANCE};
+pub use self::buffer::{PixelLayout, RgbBuffer};
+pub use self::edge::{EdgeBorder, EdgeOperator};
+pub use self::exif::{read_orientation, Orientation};
+pub use self::haar::HaarCascade;
 use self::math::*;
+pub use self::regions::detect_skin_regions;
+pub use self::video::{VideoCropSettings, VideoCropper};
+pub use self::yuv::{YuvBuffer, YuvMatrix, YuvPlane, YuvRange};
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Enters a `tracing` span until the end of the scope, a no-op without the
+// `tracing` feature
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($args:tt)*) => {
+        tracing::info_span!($($args)*).entered()
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($args:tt)*) => {
+        ()
+    };
+}
 
-const PRESCALE: bool = true;
-const PRESCALE_MIN: f64 = 400.00;
+const PRESCALE_SIZE: u32 = 400;
 const MIN_SCALE: f64 = 1.0;
 const MAX_SCALE: f64 = 1.0;
 // STEP * minscale rounded down to the next power of two should be good
@@ -28,26 +65,104 @@ const SKIN_BRIGHTNESS_MIN: f64 = 0.2;
 const SKIN_BRIGHTNESS_MAX: f64 = 1.0;
 const SKIN_THRESHOLD: f64 = 0.8;
 const SKIN_BIAS: f64 = 0.01;
+const SKIN_TONE_THRESHOLD: f64 = 0.96;
+const SKIN_LUMA_MIN: f64 = 0.08;
 
 const SATURATION_BRIGHTNESS_MIN: f64 = 0.05;
 const SATURATION_BRIGHTNESS_MAX: f64 = 0.9;
 const SATURATION_THRESHOLD: f64 = 0.4;
 const SATURATION_BIAS: f64 = 0.2;
 const SATURATION_WEIGHT: f64 = 0.1;
+const BOOST_WEIGHT: f64 = 100.0;
+
+// Brightness limits for lightness from 0 to 1 of the corrected colour
+// models. The legacy ones apply to `RGB::cie`, whose coefficients add up to
+// 1.3, so these are divided by 1.3 to accept the same neutral tones.
+const REC709_SKIN_BRIGHTNESS_MIN: f64 = 0.15;
+const REC709_SKIN_BRIGHTNESS_MAX: f64 = 0.77;
+const REC709_SATURATION_BRIGHTNESS_MIN: f64 = 0.04;
+const REC709_SATURATION_BRIGHTNESS_MAX: f64 = 0.69;
 
-pub trait Image: Sized {
+// Radius of the window over which the variance of the Laplacian is taken
+const SHARPNESS_RADIUS: usize = 2;
+
+pub trait Image {
     fn width(&self) -> u32;
     fn height(&self) -> u32;
     fn get(&self, x: u32, y: u32) -> RGB;
+
+    /// Lightness used by edge detection, on the same scale as `RGB::cie`.
+    /// Images that store lightness natively can override it.
+    fn cie(&self, x: u32, y: u32) -> f64 {
+        self.get(x, y).cie()
+    }
+
+    /// Lightness in `model`, on the scale of `ColorModel::lightness`.
+    /// Images that store lightness natively can override it, `JsCompat`
+    /// uses `Image::cie` by default.
+    fn lightness(&self, x: u32, y: u32, model: ColorModel) -> f64 {
+        match model {
+            ColorModel::JsCompat => self.cie(x, y),
+            model => model.lightness(self.get(x, y)),
+        }
+    }
+}
+
+/// Finds regions crops should include, like faces, for `Analyzer::with_detector`.
+pub trait RegionDetector: Send + Sync {
+    /// Regions in image coordinates with a confidence from 0 to 1, used as
+    /// the weight of their boosts.
+    fn detect(&self, img: &dyn Image) -> Vec<(Crop, f64)>;
 }
 
 pub trait ResizableImage<I: Image> {
     fn resize(&self, width: u32, height: u32) -> I;
+
+    /// Implementations that support several filters should override this.
+    fn resize_with_filter(&self, width: u32, height: u32, _filter: ResizeFilter) -> I {
+        self.resize(width, height)
+    }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum Error {
     ZeroSizedImage,
+    /// The pixel data ends before the last row of the image, or its
+    /// required length does not fit in memory
+    BufferTooSmall,
+    /// The stride is shorter than a row, or a row of the given width does
+    /// not fit in memory
+    InvalidStride,
+    /// `find_best_crop_for_frames` was given no frames
+    NoFrames,
+    /// A frame passed to `find_best_crop_for_frames` is not the same size as
+    /// the first one
+    FrameSizeMismatch,
+    /// The time budget ran out or the analysis was cancelled before any
+    /// crop was scored
+    Cancelled,
+    /// `required_region` is not inside the image or no crop of the requested
+    /// aspect ratio can contain it
+    RequiredRegionDoesNotFit,
+    /// Every candidate crop intersects a hard exclusion
+    AllCropsExcluded,
+    /// The cascade file could not be read or is not a supported cascade
+    InvalidCascade(String),
+    /// Writing the output failed, used by the helpers of the `image` feature
+    EncodingFailed(String),
+    /// Reading the input failed, used by the helpers of the `image` feature
+    DecodingFailed(String),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ResizeFilter {
+    /// Built-in area-average filter, does not depend on the `image` feature
+    Box,
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -58,15 +173,28 @@ pub struct RGB {
 }
 
 impl RGB {
-    pub fn new(r: u8, g: u8, b: u8) -> RGB {
+    pub const fn new(r: u8, g: u8, b: u8) -> RGB {
         RGB { r, g, b }
     }
 
+    /// Lightness of smartcrop.js, with the red and blue coefficients swapped.
+    /// `ColorModel::Rec709` uses `luminance` instead.
     pub fn cie(self: &RGB) -> f64 {
         //TODO: Change it as soon as https://github.com/jwagner/smartcrop.js/issues/77 is closed
         0.5126 * self.b as f64 + 0.7152 * self.g as f64 + 0.0722 * self.r as f64
     }
 
+    /// Rec. 709 luminance of the gamma-encoded channels, from 0 to 255.
+    pub fn luminance(self: &RGB) -> f64 {
+        0.2126 * self.r as f64 + 0.7152 * self.g as f64 + 0.0722 * self.b as f64
+    }
+
+    /// Rec. 709 luminance of the linearised channels, encoded back with the
+    /// sRGB transfer function, from 0 to 255.
+    pub fn linear_luminance(self: &RGB) -> f64 {
+        linear_to_srgb(linear_y(*self)) * 255.0
+    }
+
     pub fn saturation(self: &RGB) -> f64 {
         let maximum = f64::max(
             f64::max(self.r as f64 / 255.0, self.g as f64 / 255.0),
@@ -109,10 +237,19 @@ impl RGB {
 
 // Score contains values that classify matches
 #[derive(Clone, PartialEq, Debug)]
+#[non_exhaustive]
 pub struct Score {
     pub detail: f64,
     pub saturation: f64,
     pub skin: f64,
+    pub sharpness: f64,
+    pub boost: f64,
+    pub text: f64,
+    /// Text inside the crop unweighted by importance, scored instead of
+    /// `text` with a negative `text_weight`
+    pub text_inside: f64,
+    /// Pixels of text lines cut off by the crop edges
+    pub text_cuts: f64,
     pub total: f64,
 }
 
@@ -126,6 +263,142 @@ pub struct Crop {
 }
 
 impl Crop {
+    fn intersects(&self, other: &Crop) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+
+    fn intersection_area(&self, other: &Crop) -> u64 {
+        let overlap = |start: u32, size: u32, other_start: u32, other_size: u32| {
+            (start + size)
+                .min(other_start + other_size)
+                .saturating_sub(start.max(other_start))
+        };
+        overlap(self.x, self.width, other.x, other.width) as u64
+            * overlap(self.y, self.height, other.y, other.height) as u64
+    }
+
+    fn contains(&self, other: &Crop) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && self.x + self.width >= other.x + other.width
+            && self.y + self.height >= other.y + other.height
+    }
+
+    // Moves and shrinks the crop the least so that it is inside `bounds`
+    fn shift_into(&self, bounds: &Crop) -> Crop {
+        let width = self.width.min(bounds.width);
+        let height = self.height.min(bounds.height);
+
+        Crop {
+            x: self.x.clamp(bounds.x, bounds.x + bounds.width - width),
+            y: self.y.clamp(bounds.y, bounds.y + bounds.height - height),
+            width,
+            height,
+        }
+    }
+
+    // Moves the crop the least along one axis so that it is inside `bounds`,
+    // intersects none of `exclusions` and covers `required`
+    fn shift_off(
+        &self,
+        exclusions: &[&Crop],
+        bounds: &Crop,
+        required: Option<&Crop>,
+    ) -> Option<Crop> {
+        let allowed = |crop: &Crop| {
+            bounds.contains(crop)
+                && required.map_or(true, |r| crop.contains(r))
+                && !exclusions.iter().any(|e| crop.intersects(e))
+        };
+        if allowed(self) {
+            return Some(self.clone());
+        }
+
+        let mut shifted = vec![];
+        for e in exclusions.iter().filter(|e| self.intersects(e)) {
+            if let Some(x) = e.x.checked_sub(self.width) {
+                shifted.push(Crop { x, ..self.clone() });
+            }
+            shifted.push(Crop {
+                x: e.x + e.width,
+                ..self.clone()
+            });
+            if let Some(y) = e.y.checked_sub(self.height) {
+                shifted.push(Crop { y, ..self.clone() });
+            }
+            shifted.push(Crop {
+                y: e.y + e.height,
+                ..self.clone()
+            });
+        }
+
+        shifted
+            .into_iter()
+            .filter(allowed)
+            .min_by_key(|c| self.x.abs_diff(c.x) + self.y.abs_diff(c.y))
+    }
+
+    // Smallest crop covering both
+    fn union(&self, other: &Crop) -> Crop {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+
+        Crop {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    // Largest crop inside this one in an image scaled by `ratio`, never empty
+    fn scale_inwards(&self, ratio: f64) -> Crop {
+        let x = (self.x as f64 * ratio).ceil() as u32;
+        let y = (self.y as f64 * ratio).ceil() as u32;
+        let right = ((self.x + self.width) as f64 * ratio).floor() as u32;
+        let bottom = ((self.y + self.height) as f64 * ratio).floor() as u32;
+
+        Crop {
+            x,
+            y,
+            width: right.saturating_sub(x).max(1),
+            height: bottom.saturating_sub(y).max(1),
+        }
+    }
+
+    // Smallest crop covering this one in an image scaled by `ratio`
+    fn scale_outwards(&self, ratio: f64) -> Crop {
+        let x = (self.x as f64 * ratio).floor() as u32;
+        let y = (self.y as f64 * ratio).floor() as u32;
+        let right = ((self.x + self.width) as f64 * ratio).ceil() as u32;
+        let bottom = ((self.y + self.height) as f64 * ratio).ceil() as u32;
+
+        Crop {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+
+    // Moves the crop the least so that it covers `region`, which must fit
+    fn shift_to_contain(&self, region: &Crop) -> Crop {
+        let shift = |start: u32, size: u32, region_start: u32, region_size: u32| {
+            start
+                .min(region_start)
+                .max((region_start + region_size).saturating_sub(size))
+        };
+
+        Crop {
+            x: shift(self.x, self.width, region.x, region.width),
+            y: shift(self.y, self.height, region.y, region.height),
+            ..self.clone()
+        }
+    }
+
     fn scale(&self, ratio: f64) -> Crop {
         Crop {
             x: (self.x as f64 * ratio).round() as u32,
@@ -137,9 +410,13 @@ impl Crop {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct ScoredCrop {
     pub crop: Crop,
     pub score: Score,
+    /// The analysis was interrupted, this is the best of the crops scored
+    /// until then
+    pub partial: bool,
 }
 
 impl ScoredCrop {
@@ -147,61 +424,324 @@ impl ScoredCrop {
         ScoredCrop {
             crop: self.crop.scale(ratio),
             score: self.score.clone(),
+            partial: self.partial,
         }
     }
 }
 
-pub struct CropSettings {}
+/// Precision of the feature channels between detection and scoring
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ChannelPrecision {
+    /// Channels are rounded to 8 bits after every step, as smartcrop.js
+    /// does. Results are identical to it for images the prescale does not
+    /// shrink, shrunk ones depend on the resampling filter
+    U8,
+    /// Channels are kept unrounded from detection to scoring, which
+    /// preserves weak features of low-contrast images. The prescaled image
+    /// itself is still 8-bit RGB
+    F32,
+}
 
-impl CropSettings {
-    pub fn default() -> CropSettings {
-        CropSettings {}
+/// Strategy for choosing the candidate crops that get scored
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CandidateSearch {
+    /// Every position on an 8px grid, as in smartcrop.js
+    Grid,
+    /// Positions on a `coarse_step` grid, then the `top_k` best are refined
+    /// with halved steps down to 1px in the prescaled image. Positions more
+    /// precisely than `Grid`, it is not faster as detection dominates the
+    /// analysis time
+    CoarseToFine {
+        coarse_step: NonZeroU32,
+        top_k: NonZeroU32,
+    },
+}
+
+/// Formula for the lightness used by edge detection and the brightness limits
+/// of the skin and saturation channels
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorModel {
+    /// `RGB::cie` as in smartcrop.js, keeps results of earlier versions
+    JsCompat,
+    /// `RGB::luminance`
+    Rec709,
+    /// `RGB::linear_luminance`
+    LinearRec709,
+}
+
+impl ColorModel {
+    /// Lightness of the color from 0 to 255, up to 331.5 for `JsCompat`.
+    pub fn lightness(self, color: RGB) -> f64 {
+        match self {
+            ColorModel::JsCompat => color.cie(),
+            ColorModel::Rec709 => color.luminance(),
+            ColorModel::LinearRec709 => color.linear_luminance(),
+        }
+    }
+
+    // Lightness of white, the largest one of the model
+    fn white(self) -> f64 {
+        self.lightness(RGB::new(255, 255, 255))
+    }
+
+    fn skin_brightness(self) -> (f64, f64) {
+        match self {
+            ColorModel::JsCompat => (SKIN_BRIGHTNESS_MIN, SKIN_BRIGHTNESS_MAX),
+            _ => (REC709_SKIN_BRIGHTNESS_MIN, REC709_SKIN_BRIGHTNESS_MAX),
+        }
+    }
+
+    fn saturation_brightness(self) -> (f64, f64) {
+        match self {
+            ColorModel::JsCompat => (SATURATION_BRIGHTNESS_MIN, SATURATION_BRIGHTNESS_MAX),
+            _ => (
+                REC709_SATURATION_BRIGHTNESS_MIN,
+                REC709_SATURATION_BRIGHTNESS_MAX,
+            ),
+        }
     }
 }
-#[derive(Debug)]
+
+/// Classifier of the skin channel.
+///
+/// Coverage is given for the ten tones of the Monk Skin Tone scale.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SkinModel {
+    /// Distance to a single skin colour, as in smartcrop.js. Misses the
+    /// lightest and darkest tones and most shades of tones 2 to 4
+    Reference,
+    /// Distance to the closest of six hand-picked tones from light to dark.
+    /// Detects the darkest tones best, misses the lightest tone and some
+    /// shades of tones 2 to 6
+    MultiTone,
+    /// Region of the YCbCr chroma plane, independent of brightness. Misses
+    /// the nearly neutral tones 9 and 10 and accepts wood and sand colours
+    Chroma,
+    /// The more confident of `MultiTone` and `Chroma`, recommended unless
+    /// results have to match smartcrop.js. Detects every tone but the most
+    /// neutral shades of the lightest one, which are as gray as off-white
+    /// surfaces. Accepts wood and sand colours like `Chroma`
+    Combined,
+}
+
+/// How feature channels of the frames of an animation are combined
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FrameAggregation {
+    Max,
+    Mean,
+}
+
+#[derive(Clone, Debug)]
+pub struct CropSettings {
+    /// Filter used by `thumbnail` to scale the best crop to the requested size
+    pub resize_filter: ResizeFilter,
+    /// Shorter side of the image used for analysis, larger images are shrunk
+    /// to it first. `None` analyses at full resolution.
+    ///
+    /// Time and memory grow with the number of analysed pixels, but a
+    /// larger size keeps fine detail of big images that gets averaged away
+    /// at the default of 400px.
+    pub prescale: Option<NonZeroU32>,
+    /// Filter used to shrink the image before analysis
+    pub prescale_filter: ResizeFilter,
+    /// Combination of the feature channels of animation frames
+    pub frame_aggregation: FrameAggregation,
+    /// Only every n-th frame of an animation is analysed
+    pub frame_step: NonZeroU32,
+    /// Rounding of the feature channels, `U8` keeps smartcrop.js results
+    pub channel_precision: ChannelPrecision,
+    /// Positions of the scored candidate crops
+    pub candidate_search: CandidateSearch,
+    /// Maximal duration of a single analysis, see `Error::Cancelled`
+    pub time_budget: Option<Duration>,
+    /// Region of the image that must be fully inside the crop
+    pub required_region: Option<Crop>,
+    /// When no crop of the requested aspect ratio can contain
+    /// `required_region`, return the smallest crop of the closest aspect
+    /// ratio containing it instead of `Error::RequiredRegionDoesNotFit`
+    pub required_region_fallback: bool,
+    /// Regions crops should stay clear of, like watermarks and captions
+    pub exclusions: Vec<Exclusion>,
+    /// Uniform borders, letterbox bars and padding of the first frame are
+    /// left out of the crops. Channels of border pixels may differ by this
+    /// much, `None` disables the detection
+    pub border_tolerance: Option<u8>,
+    /// Weight of the sharpness channel, which prefers in-focus regions over
+    /// blurred backgrounds. Detail and skin have weights of 0.2 and 1.8. With
+    /// 0 the variance of the Laplacian is skipped, saving its cost
+    pub sharpness_weight: f64,
+    pub edge_operator: EdgeOperator,
+    /// Standard deviation of a Gaussian blur of the lightness before edge
+    /// detection, suppresses noise
+    pub edge_blur: Option<f64>,
+    pub edge_border: EdgeBorder,
+    pub skin_model: SkinModel,
+    pub color_model: ColorModel,
+    /// Average colours in linear light when prescaling with
+    /// `ResizeFilter::Box`. Only the prescale is affected: detection works
+    /// on the re-encoded colours in the `color_model` (`LinearRec709` for
+    /// linear luminance), the native lightness of the image is not used and
+    /// feature channels are still down-sampled as they are.
+    pub linear_light: bool,
+    /// Regions crops should preferably include and centre on, like faces
+    pub boosts: Vec<Boost>,
+    /// Weight of the boosts added for face-like blobs of skin found by
+    /// `detect_skin_regions`, 0 adds no boosts and skips the blob search
+    pub skin_region_boost: f64,
+    /// Weight of the text channel, positive to keep headlines and captions
+    /// in the crop, negative to penalise text anywhere inside it. Text is
+    /// only looked for when this or `text_cut_penalty` is set
+    pub text_weight: f64,
+    /// Penalty for every pixel of a line of text cut off by an edge of the
+    /// crop, on the smaller side of the cut
+    pub text_cut_penalty: f64,
+}
+
+/// Region of the image that raises the score of crops including it, more so
+/// when it is in their centre.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Boost {
+    pub region: Crop,
+    /// From 0 to 1, overlapping boosts add up to at most 1
+    pub weight: f64,
+}
+
+/// Region of the image that lowers the score of crops including it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Exclusion {
+    pub region: Crop,
+    /// Penalty for every pixel of the region inside a crop, a pixel of
+    /// detailed skin adds about 2 to the score
+    pub weight: f64,
+    /// Crops intersecting the region are not considered at all
+    pub hard: bool,
+}
+
+impl Default for CropSettings {
+    fn default() -> CropSettings {
+        CropSettings {
+            resize_filter: ResizeFilter::Lanczos3,
+            prescale: NonZeroU32::new(PRESCALE_SIZE),
+            prescale_filter: ResizeFilter::Box,
+            frame_aggregation: FrameAggregation::Max,
+            frame_step: NonZeroU32::new(1).unwrap(),
+            channel_precision: ChannelPrecision::U8,
+            candidate_search: CandidateSearch::Grid,
+            time_budget: None,
+            required_region: None,
+            required_region_fallback: false,
+            exclusions: vec![],
+            border_tolerance: None,
+            sharpness_weight: 0.0,
+            edge_operator: EdgeOperator::Laplacian,
+            edge_blur: None,
+            edge_border: EdgeBorder::Lightness,
+            skin_model: SkinModel::Reference,
+            color_model: ColorModel::JsCompat,
+            linear_light: false,
+            boosts: vec![],
+            skin_region_boost: 0.0,
+            text_weight: 0.0,
+            text_cut_penalty: 0.0,
+        }
+    }
+}
+
+// Feature channels stored as contiguous row-major planes with values from 0
+// to 255, whole numbers only unless the precision is `F32`
+#[derive(Clone, Debug)]
 struct ImageMap {
     width: u32,
     height: u32,
-
-    pixels: Vec<Vec<RGB>>,
+    precision: ChannelPrecision,
+
+    skin: Vec<f32>,
+    detail: Vec<f32>,
+    saturation: Vec<f32>,
+    sharpness: Vec<f32>,
+    text: Vec<f32>,
+    // Lines of text in the coordinates of the full resolution map, kept as
+    // is by down sampling
+    text_lines: Vec<Crop>,
+    // Boosts and the penalty of the exclusion regions, not detected features
+    boost: Vec<f32>,
+    exclusion: Vec<f32>,
 }
 
 impl ImageMap {
-    fn new(width: u32, height: u32) -> ImageMap {
-        let white = RGB::new(255, 255, 255);
-        let pixels = vec![vec![white; height as usize]; width as usize];
+    fn with_precision(width: u32, height: u32, precision: ChannelPrecision) -> ImageMap {
+        let plane = vec![255.0; width as usize * height as usize];
         ImageMap {
             width,
             height,
-            pixels,
+            precision,
+            skin: plane.clone(),
+            detail: plane.clone(),
+            saturation: plane,
+            sharpness: vec![0.0; width as usize * height as usize],
+            text: vec![0.0; width as usize * height as usize],
+            text_lines: vec![],
+            boost: vec![0.0; width as usize * height as usize],
+            exclusion: vec![0.0; width as usize * height as usize],
+        }
+    }
+
+    fn add_boost(&mut self, region: &Crop, weight: f64) {
+        for y in region.y..region.y + region.height {
+            let row = self.index(region.x, y);
+            for boost in &mut self.boost[row..row + region.width as usize] {
+                *boost = (*boost + (weight * 255.0) as f32).clamp(0.0, 255.0);
+            }
+        }
+    }
+
+    fn add_exclusion(&mut self, region: &Crop, weight: f64) {
+        for y in region.y..region.y + region.height {
+            let row = self.index(region.x, y);
+            for penalty in &mut self.exclusion[row..row + region.width as usize] {
+                *penalty += weight as f32;
+            }
+        }
+    }
+
+    // Converts a channel value to the stored precision
+    fn quantize(&self, value: f64) -> f32 {
+        match self.precision {
+            ChannelPrecision::U8 => bounds(value) as f32,
+            ChannelPrecision::F32 => value.clamp(0.0, 255.0) as f32,
         }
     }
 
-    fn set(&mut self, x: u32, y: u32, color: RGB) {
-        self.pixels[x as usize][y as usize] = color
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
     }
 
     fn get(&self, x: u32, y: u32) -> RGB {
-        self.pixels[x as usize][y as usize]
+        let i = self.index(x, y);
+        RGB::new(
+            bounds(self.skin[i] as f64),
+            bounds(self.detail[i] as f64),
+            bounds(self.saturation[i] as f64),
+        )
     }
 
-    fn down_sample(self, factor: u32) -> Self {
+    fn down_sample(self, factor: u32, monitor: &Monitor) -> Self {
+        let _span = span!(
+            "down_sample",
+            factor,
+            width = self.width,
+            height = self.height
+        );
         let width = (self.width as f64 / factor as f64).floor() as u32;
         let height = (self.height as f64 / factor as f64).floor() as u32;
-        let mut output = ImageMap::new(width, height);
-        //        let data = output.data;
+        let mut output = ImageMap::with_precision(width, height, self.precision);
         let ifactor2: f64 = 1.0 / (factor as f64 * factor as f64);
+        let factor = factor as usize;
+        let stride = self.width as usize;
 
-        let max = |a, b| {
-            if a > b {
-                a
-            } else {
-                b
-            }
-        };
-
-        for y in 0..height {
-            for x in 0..width {
+        for y in 0..height as usize {
+            monitor.report(AnalysisStage::DownSample, y, height as usize);
+            for x in 0..width as usize {
                 let mut r: f64 = 0.0;
                 let mut g: f64 = 0.0;
                 let mut b: f64 = 0.0;
@@ -209,45 +749,243 @@ impl ImageMap {
                 let mut mr: f64 = 0.0;
                 let mut mg: f64 = 0.0;
 
-                for v in 0..factor as u32 {
-                    for u in 0..factor {
-                        let ix = x * factor + u;
-                        let iy = y * factor + v;
-                        let icolor = self.get(ix, iy);
-
-                        r += icolor.r as f64;
-                        g += icolor.g as f64;
-                        b += icolor.b as f64;
-                        mr = max(mr, icolor.r as f64);
-                        mg = max(mg, icolor.g as f64);
+                let mut s: f64 = 0.0;
+                let mut t: f64 = 0.0;
+                let mut a: f64 = 0.0;
+                let mut e: f64 = 0.0;
+
+                for v in 0..factor {
+                    let row = (y * factor + v) * stride + x * factor;
+                    for i in row..row + factor {
+                        let (ir, ig) = (self.skin[i] as f64, self.detail[i] as f64);
+
+                        r += ir;
+                        g += ig;
+                        b += self.saturation[i] as f64;
+                        s += self.sharpness[i] as f64;
+                        t += self.text[i] as f64;
+                        a += self.boost[i] as f64;
+                        e += self.exclusion[i] as f64;
+                        mr = mr.max(ir);
+                        mg = mg.max(ig);
                     }
                 }
 
                 // this is some funky magic to preserve detail a bit more for
                 // skin (r) and detail (g). saturation (b) does not get this boost.
-                output.set(
-                    x,
-                    y,
-                    RGB::new(
-                        (r * ifactor2 * 0.5 + mr * 0.5).round() as u8,
-                        (g * ifactor2 * 0.7 + mg * 0.3).round() as u8,
-                        (b * ifactor2).round() as u8,
-                    ),
-                )
+                let o = y * width as usize + x;
+                output.skin[o] = output.quantize(r * ifactor2 * 0.5 + mr * 0.5);
+                output.detail[o] = output.quantize(g * ifactor2 * 0.7 + mg * 0.3);
+                output.saturation[o] = output.quantize(b * ifactor2);
+                output.sharpness[o] = output.quantize(s * ifactor2);
+                output.text[o] = output.quantize(t * ifactor2);
+                output.boost[o] = (a * ifactor2) as f32;
+                output.exclusion[o] = (e * ifactor2) as f32;
             }
         }
+        monitor.report(AnalysisStage::DownSample, 1, 1);
 
+        output.text_lines = self.text_lines;
         output
     }
 }
 
+// Combines feature maps of several frames of the same size
+struct FrameAggregator {
+    aggregation: FrameAggregation,
+    precision: ChannelPrecision,
+    frames: u32,
+    width: u32,
+    height: u32,
+    channels: Vec<[f64; 5]>,
+}
+
+impl FrameAggregator {
+    fn new(aggregation: FrameAggregation, precision: ChannelPrecision) -> FrameAggregator {
+        FrameAggregator {
+            aggregation,
+            precision,
+            frames: 0,
+            width: 0,
+            height: 0,
+            channels: vec![],
+        }
+    }
+
+    fn add(&mut self, o: &ImageMap) {
+        if self.frames == 0 {
+            self.width = o.width;
+            self.height = o.height;
+            self.channels = vec![[0.0; 5]; o.width as usize * o.height as usize];
+        }
+        self.frames += 1;
+
+        for (i, channels) in self.channels.iter_mut().enumerate() {
+            let values = [
+                o.skin[i],
+                o.detail[i],
+                o.saturation[i],
+                o.sharpness[i],
+                o.text[i],
+            ];
+            for (c, v) in channels.iter_mut().zip(&values) {
+                let v = *v as f64;
+                *c = match self.aggregation {
+                    FrameAggregation::Max => c.max(v),
+                    FrameAggregation::Mean => *c + v,
+                };
+            }
+        }
+    }
+
+    fn finish(self) -> ImageMap {
+        let mut o = ImageMap::with_precision(self.width, self.height, self.precision);
+        let divisor = match self.aggregation {
+            FrameAggregation::Max => 1.0,
+            FrameAggregation::Mean => self.frames as f64,
+        };
+
+        for (i, [r, g, b, s, t]) in self.channels.into_iter().enumerate() {
+            o.skin[i] = o.quantize(r / divisor);
+            o.detail[i] = o.quantize(g / divisor);
+            o.saturation[i] = o.quantize(b / divisor);
+            o.sharpness[i] = o.quantize(s / divisor);
+            o.text[i] = o.quantize(t / divisor);
+        }
+
+        o
+    }
+}
+
+/// Stage of an analysis reported to the progress observer
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AnalysisStage {
+    /// Only runs with a non-zero `skin_region_boost` or detectors added by
+    /// `Analyzer::with_detector`, one step per detection
+    RegionDetection,
+    Prescale,
+    EdgeDetection,
+    SkinDetection,
+    SaturationDetection,
+    /// Only runs with a non-zero `sharpness_weight`
+    SharpnessDetection,
+    /// Only runs with a non-zero `text_weight` or `text_cut_penalty`
+    TextDetection,
+    DownSample,
+    Scoring,
+}
+
+type ProgressObserver = dyn Fn(AnalysisStage, f64) + Send + Sync;
+
+// Lets the long running loops of an analysis stop early and report progress
+#[derive(Default)]
+struct Monitor<'a> {
+    deadline: Option<Instant>,
+    cancel_flag: Option<&'a AtomicBool>,
+    progress: Option<&'a ProgressObserver>,
+    last_report: Cell<Option<(AnalysisStage, u32)>>,
+}
+
+impl<'a> Monitor<'a> {
+    // Reports progress and checks for cancellation, called once per
+    // iteration of the main loop of a stage
+    fn step(&self, stage: AnalysisStage, done: usize, total: usize) -> Result<(), Error> {
+        self.report(stage, done, total);
+        self.check()
+    }
+
+    // Calls the observer at most once per percent of every stage
+    fn report(&self, stage: AnalysisStage, done: usize, total: usize) {
+        let progress = match self.progress {
+            Some(progress) => progress,
+            None => return,
+        };
+
+        let fraction = if total == 0 {
+            1.0
+        } else {
+            (done as f64 / total as f64).min(1.0)
+        };
+        let percent = (fraction * 100.0) as u32;
+        if self.last_report.get() != Some((stage, percent)) {
+            self.last_report.set(Some((stage, percent)));
+            progress(stage, fraction);
+        }
+    }
+
+    // Checks the same deadline and cancel flag, for steps that would report
+    // the progress of a stage twice
+    fn without_progress(&self) -> Monitor<'a> {
+        Monitor {
+            deadline: self.deadline,
+            cancel_flag: self.cancel_flag,
+            ..Monitor::default()
+        }
+    }
+
+    // Runs steps without a deadline, cancel flag or observer, which
+    // therefore never fail
+    fn unmonitored<T>(run: impl FnOnce(&Monitor) -> Result<T, Error>) -> T {
+        run(&Monitor::default()).expect("Unmonitored analysis is never cancelled")
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let cancelled = self
+            .cancel_flag
+            .is_some_and(|flag| flag.load(Ordering::Relaxed));
+        let expired = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+
+        if cancelled || expired {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 pub struct Analyzer {
     settings: CropSettings,
+    cancel_flag: Option<Arc<AtomicBool>>,
+    progress: Option<Arc<ProgressObserver>>,
+    detectors: Vec<Arc<dyn RegionDetector>>,
 }
 
 impl Analyzer {
     pub fn new(settings: CropSettings) -> Analyzer {
-        Analyzer { settings }
+        Analyzer {
+            settings,
+            cancel_flag: None,
+            progress: None,
+            detectors: vec![],
+        }
+    }
+
+    /// `observer` receives the current stage and the completed fraction of
+    /// it, at most about a hundred times per stage.
+    pub fn with_progress<F>(mut self, observer: F) -> Analyzer
+    where
+        F: Fn(AnalysisStage, f64) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(observer));
+        self
+    }
+
+    /// Running analyses stop soon after `flag` is set.
+    ///
+    /// Cancellation during scoring returns the best crop found so far with
+    /// `partial` set, earlier it fails with `Error::Cancelled`. The flag is
+    /// never reset by the analyzer.
+    pub fn with_cancel_flag(mut self, flag: Arc<AtomicBool>) -> Analyzer {
+        self.cancel_flag = Some(flag);
+        self
+    }
+
+    /// Regions found by `detector` on the first frame become boosts.
+    pub fn with_detector<D: RegionDetector + 'static>(mut self, detector: D) -> Analyzer {
+        self.detectors.push(Arc::new(detector));
+        self
     }
 
     pub fn find_best_crop<I: Image + ResizableImage<RI>, RI: Image>(
@@ -256,127 +994,670 @@ impl Analyzer {
         width: NonZeroU32,
         height: NonZeroU32,
     ) -> Result<ScoredCrop, Error> {
-        if img.width() == 0 || img.height() == 0 {
+        self.find_best_crop_for_frames(std::iter::once(img), width, height)
+    }
+
+    /// Finds a single crop that works for all the frames of an animation.
+    ///
+    /// Feature channels of every `frame_step`-th frame are combined as set by
+    /// `frame_aggregation`. All the frames must have the same dimensions.
+    pub fn find_best_crop_for_frames<'f, I, RI, F>(
+        &self,
+        frames: F,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> Result<ScoredCrop, Error>
+    where
+        I: Image + ResizableImage<RI> + 'f,
+        RI: Image,
+        F: IntoIterator<Item = &'f I>,
+    {
+        let mut frames = frames
+            .into_iter()
+            .step_by(self.settings.frame_step.get() as usize)
+            .peekable();
+        let first = match frames.peek() {
+            Some(img) => *img,
+            None => return Err(Error::NoFrames),
+        };
+        let (img_width, img_height) = (first.width(), first.height());
+
+        self.find_best_crop_for_map(first, width, height, |monitor| {
+            let mut aggregator = FrameAggregator::new(
+                self.settings.frame_aggregation,
+                self.settings.channel_precision,
+            );
+            for img in frames {
+                if img.width() != img_width || img.height() != img_height {
+                    return Err(Error::FrameSizeMismatch);
+                }
+                aggregator.add(&self.frame_map(img, monitor)?);
+            }
+            Ok(aggregator.finish())
+        })
+    }
+
+    // Finds the best crop of `img` in the feature map returned by `map`,
+    // which is called once the regions of `img` are detected
+    pub(crate) fn find_best_crop_for_map<I, M>(
+        &self,
+        img: &I,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        map: M,
+    ) -> Result<ScoredCrop, Error>
+    where
+        I: Image,
+        M: FnOnce(&Monitor) -> Result<ImageMap, Error>,
+    {
+        let (img_width, img_height) = (img.width(), img.height());
+        if img_width == 0 || img_height == 0 {
             return Err(Error::ZeroSizedImage);
         }
+        if let Some(ref region) = self.settings.required_region {
+            let image = Crop {
+                x: 0,
+                y: 0,
+                width: img_width,
+                height: img_height,
+            };
+            if region.width == 0 || region.height == 0 || !image.contains(region) {
+                return Err(Error::RequiredRegionDoesNotFit);
+            }
+        }
+
+        let _span = span!(
+            "find_best_crop",
+            image_width = img_width,
+            image_height = img_height,
+            width = width.get(),
+            height = height.get(),
+        );
+        let monitor = self.monitor();
+        let content = match self.settings.border_tolerance {
+            Some(tolerance) => {
+                let _span = span!("detect_content_bounds");
+                monitor.check()?;
+                let bounds = border::content_bounds(img, tolerance);
+                // The required region wins over the detected borders
+                Some(match self.settings.required_region {
+                    Some(ref region) => bounds.union(region),
+                    None => bounds,
+                })
+            }
+            None => None,
+        };
+        let mut boosts = vec![];
+        let skin_regions = self.settings.skin_region_boost != 0.0;
+        let detections = self.detectors.len() + skin_regions as usize;
+        let mut detected = 0;
+        if skin_regions {
+            let _span = span!("detect_skin_regions");
+            monitor.step(AnalysisStage::RegionDetection, detected, detections)?;
+            let regions = regions::skin_regions(img, &self.settings, &monitor.without_progress())?;
+            boosts.extend(regions.into_iter().map(|region| Boost {
+                region,
+                weight: self.settings.skin_region_boost,
+            }));
+            detected += 1;
+        }
+        for detector in &self.detectors {
+            let _span = span!("detect_regions");
+            monitor.step(AnalysisStage::RegionDetection, detected, detections)?;
+            boosts.extend(
+                (detector.detect(img).into_iter())
+                    .filter(|(region, _)| region.width > 0 && region.height > 0)
+                    .map(|(region, confidence)| Boost {
+                        region,
+                        weight: confidence.clamp(0.0, 1.0),
+                    }),
+            );
+            detected += 1;
+        }
+        if detections > 0 {
+            monitor.step(AnalysisStage::RegionDetection, detected, detections)?;
+        }
+        let detected = DetectedRegions { content, boosts };
+        let (content_width, content_height) = match detected.content {
+            Some(ref bounds) => (bounds.width as f64, bounds.height as f64),
+            None => (img_width as f64, img_height as f64),
+        };
 
         let width = width.get() as f64;
         let height = height.get() as f64;
 
-        let scale = f64::min((img.width() as f64) / width, (img.height() as f64) / height);
+        let scale = f64::min(content_width / width, content_height / height);
+
+        let prescalefactor = self.prescale_factor(img_width, img_height);
+        let crop_width = (width * scale * prescalefactor).max(1.0).round() as u32;
+        let crop_height = (height * scale * prescalefactor).max(1.0).round() as u32;
+        let real_min_scale = calculate_real_min_scale(scale);
+
+        analyse_prescaled(
+            &self.settings,
+            map(&monitor)?,
+            (img_width as f64, img_height as f64),
+            (crop_width, crop_height),
+            real_min_scale,
+            &detected,
+            &monitor,
+        )
+    }
+
+    // Monitor of a single analysis, its time budget starts now
+    pub(crate) fn monitor(&self) -> Monitor<'_> {
+        Monitor {
+            deadline: self
+                .settings
+                .time_budget
+                .map(|budget| Instant::now() + budget),
+            cancel_flag: self.cancel_flag.as_deref(),
+            progress: self.progress.as_deref(),
+            ..Monitor::default()
+        }
+    }
+
+    // Ratio of the prescaled size to the size of the image, 1 without a
+    // prescale
+    fn prescale_factor(&self, img_width: u32, img_height: u32) -> f64 {
+        match self.settings.prescale {
+            Some(prescale) => {
+                let f = prescale.get() as f64 / f64::min(img_width as f64, img_height as f64);
+                f.min(1.0)
+            }
+            None => 1.0,
+        }
+    }
+
+    // Feature channels of a single frame, prescaled as set
+    pub(crate) fn frame_map<I, RI>(&self, img: &I, monitor: &Monitor) -> Result<ImageMap, Error>
+    where
+        I: Image + ResizableImage<RI>,
+        RI: Image,
+    {
+        if self.settings.prescale.is_none() {
+            return feature_map(img, &self.settings, monitor);
+        }
 
         // resize image for faster processing
-        if PRESCALE {
-            let f = PRESCALE_MIN / f64::min(img.width() as f64, img.height() as f64);
-            let prescalefactor = f.min(1.0);
+        let prescalefactor = self.prescale_factor(img.width(), img.height());
+        let new_width = ((img.width() as f64) * prescalefactor).round() as u32;
+        let new_height = (prescalefactor * img.height() as f64).round() as u32;
+
+        monitor.step(AnalysisStage::Prescale, 0, 1)?;
+        let filter = self.settings.prescale_filter;
+        match filter {
+            ResizeFilter::Box => {
+                let prescaled = {
+                    let _span = span!("resize", width = new_width, height = new_height, ?filter);
+                    if self.settings.linear_light {
+                        resize::box_prescale_linear(img, new_width, new_height, monitor)?
+                    } else {
+                        resize::box_prescale(
+                            img,
+                            new_width,
+                            new_height,
+                            self.settings.color_model,
+                            monitor,
+                        )?
+                    }
+                };
+                monitor.step(AnalysisStage::Prescale, 1, 1)?;
+                feature_map(&prescaled, &self.settings, monitor)
+            }
+            filter => {
+                let prescaled = {
+                    let _span = span!("resize", width = new_width, height = new_height, ?filter);
+                    img.resize_with_filter(new_width, new_height, filter)
+                };
+                monitor.step(AnalysisStage::Prescale, 1, 1)?;
+                feature_map(&prescaled, &self.settings, monitor)
+            }
+        }
+    }
+}
 
-            let crop_width = (width * scale * prescalefactor).max(1.0).round() as u32;
-            let crop_height = (height * scale * prescalefactor).max(1.0).round() as u32;
-            let real_min_scale = calculate_real_min_scale(scale);
+fn analyse_prescaled(
+    cs: &CropSettings,
+    mut o: ImageMap,
+    (old_width, old_height): (f64, f64),
+    (crop_width, crop_height): (u32, u32),
+    real_min_scale: f64,
+    detected: &DetectedRegions,
+    monitor: &Monitor,
+) -> Result<ScoredCrop, Error> {
+    let post_scale_w = o.width as f64 / old_width;
+    let post_scale_h = o.height as f64 / old_height;
+    let post_scale_factor = f64::max(post_scale_w, post_scale_h);
+    let regions = MapRegions::new(cs, detected, post_scale_factor, o.width, o.height);
+
+    // Rounding may make the crop a pixel larger than the scaled content
+    let bounds = regions.bounds(o.width, o.height);
+    let crop_width = crop_width.min(bounds.width);
+    let crop_height = crop_height.min(bounds.height);
+    assert!(bounds.width == crop_width || bounds.height == crop_height);
+    for (region, weight) in &regions.boosts {
+        o.add_boost(region, *weight);
+    }
+    for (exclusion, region) in cs.exclusions.iter().zip(&regions.exclusions) {
+        o.add_exclusion(region, exclusion.weight);
+    }
+    if cs.text_cut_penalty != 0.0 {
+        o.text_lines = text::text_lines(&o.text, o.width, o.height);
+    }
 
-            let new_width = ((img.width() as f64) * prescalefactor).round() as u32;
-            let new_height = (prescalefactor * img.height() as f64).round() as u32;
+    let top_crop = analyse_map(
+        cs,
+        o,
+        NonZeroU32::new(crop_width).unwrap(),
+        NonZeroU32::new(crop_height).unwrap(),
+        real_min_scale,
+        &regions,
+        monitor,
+    )?;
+
+    let mut top_crop = top_crop.scale(1.0 / post_scale_factor);
+    if let Some(ref region) = cs.required_region {
+        // Rounding when scaling back may leave the region a pixel outside
+        top_crop.crop.width = top_crop.crop.width.min(old_width as u32);
+        top_crop.crop.height = top_crop.crop.height.min(old_height as u32);
+        top_crop.crop = top_crop.crop.shift_to_contain(region);
+    } else if let Some(ref content) = detected.content {
+        top_crop.crop = top_crop.crop.shift_into(content);
+    }
 
-            let old_width = img.width() as f64;
-            let old_height = img.height() as f64;
+    // Rounding when scaling back may also move the crop onto a hard
+    // exclusion, which was only avoided in the feature map
+    let hard_exclusions: Vec<&Crop> = (cs.exclusions.iter())
+        .filter(|e| e.hard)
+        .map(|e| &e.region)
+        .collect();
+    let bounds = detected.content.clone().unwrap_or(Crop {
+        x: 0,
+        y: 0,
+        width: old_width as u32,
+        height: old_height as u32,
+    });
+    top_crop.crop = (top_crop.crop)
+        .shift_off(&hard_exclusions, &bounds, cs.required_region.as_ref())
+        .ok_or(Error::AllCropsExcluded)?;
+
+    Ok(top_crop)
+}
 
-            let img = img.resize(new_width, new_height);
+// Regions found in the image by the analysis itself
+#[derive(Default)]
+struct DetectedRegions {
+    content: Option<Crop>,
+    boosts: Vec<Boost>,
+}
 
-            assert!(img.width() == crop_width || img.height() == crop_height);
-            let top_crop = analyse(
-                &self.settings,
-                &img,
-                NonZeroU32::new(crop_width).unwrap(),
-                NonZeroU32::new(crop_height).unwrap(),
-                real_min_scale,
-            );
+// Regions of the settings and detected ones in the coordinates of a feature
+// map
+#[derive(Default)]
+struct MapRegions {
+    content: Option<Crop>,
+    required: Option<Crop>,
+    boosts: Vec<(Crop, f64)>,
+    exclusions: Vec<Crop>,
+    hard_exclusions: Vec<Crop>,
+}
 
-            let post_scale_w = img.width() as f64 / old_width;
-            let post_scale_h = img.height() as f64 / old_height;
-            let post_scale_factor = f64::max(post_scale_w, post_scale_h);
+impl MapRegions {
+    fn new(
+        cs: &CropSettings,
+        detected: &DetectedRegions,
+        ratio: f64,
+        width: u32,
+        height: u32,
+    ) -> MapRegions {
+        let scale = |region: &Crop| {
+            let scaled = region.scale_outwards(ratio);
+            let x = scaled.x.min(width);
+            let y = scaled.y.min(height);
+            Crop {
+                x,
+                y,
+                width: scaled.width.min(width - x),
+                height: scaled.height.min(height - y),
+            }
+        };
 
-            Ok(top_crop.scale(1.0 / post_scale_factor))
-        } else {
-            let crop_width = (width * scale).round() as u32;
-            let crop_height = (height * scale).round() as u32;
-            let real_min_scale = calculate_real_min_scale(scale);
-
-            assert!(img.width() == crop_width || img.height() == crop_height);
-            let top_crop = analyse(
-                &self.settings,
-                img,
-                NonZeroU32::new(crop_width).unwrap(),
-                NonZeroU32::new(crop_height).unwrap(),
-                real_min_scale,
-            );
-            Ok(top_crop)
+        MapRegions {
+            content: (detected.content.as_ref())
+                .map(|c| c.scale_inwards(ratio).shift_into(&scale(c))),
+            required: cs.required_region.as_ref().map(scale),
+            boosts: (cs.boosts.iter().chain(&detected.boosts))
+                .map(|b| (scale(&b.region), b.weight))
+                .collect(),
+            exclusions: cs.exclusions.iter().map(|e| scale(&e.region)).collect(),
+            hard_exclusions: cs
+                .exclusions
+                .iter()
+                .filter(|e| e.hard)
+                .map(|e| scale(&e.region))
+                .collect(),
         }
     }
+
+    // Area candidates are generated in
+    fn bounds(&self, width: u32, height: u32) -> Crop {
+        self.content.clone().unwrap_or(Crop {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        })
+    }
+
+    fn allows(&self, crop: &Crop) -> bool {
+        self.required.as_ref().map_or(true, |r| crop.contains(r))
+            && !self.hard_exclusions.iter().any(|e| crop.intersects(e))
+    }
 }
 
 fn calculate_real_min_scale(scale: f64) -> f64 {
-    f64::min(MAX_SCALE, f64::max(1.0 / scale, MIN_SCALE))
+    (1.0 / scale).clamp(MIN_SCALE, MAX_SCALE)
 }
 
+#[cfg(test)]
 fn analyse<I: Image>(
-    _cs: &CropSettings,
+    cs: &CropSettings,
     img: &I,
     crop_width: NonZeroU32,
     crop_height: NonZeroU32,
     real_min_scale: f64,
 ) -> ScoredCrop {
-    assert!(img.width() >= crop_width.get());
-    assert!(img.height() >= crop_height.get());
+    Monitor::unmonitored(|monitor| {
+        analyse_map(
+            cs,
+            feature_map(img, cs, monitor)?,
+            crop_width,
+            crop_height,
+            real_min_scale,
+            &MapRegions::new(
+                cs,
+                &DetectedRegions::default(),
+                1.0,
+                img.width(),
+                img.height(),
+            ),
+            monitor,
+        )
+    })
+}
 
-    let mut o = ImageMap::new(img.width(), img.height());
+fn analyse_map(
+    cs: &CropSettings,
+    o: ImageMap,
+    crop_width: NonZeroU32,
+    crop_height: NonZeroU32,
+    real_min_scale: f64,
+    regions: &MapRegions,
+    monitor: &Monitor,
+) -> Result<ScoredCrop, Error> {
+    let (width, height) = (o.width, o.height);
+    let bounds = regions.bounds(width, height);
+    assert!(bounds.width >= crop_width.get());
+    assert!(bounds.height >= crop_height.get());
+
+    let step = match cs.candidate_search {
+        CandidateSearch::Grid => STEP,
+        CandidateSearch::CoarseToFine { coarse_step, .. } => coarse_step.get() as f64,
+    };
+    let candidates = {
+        let _span = span!("generate_candidates", step, count = tracing::field::Empty);
+        let mut candidates = grid_crops(
+            &bounds,
+            crop_width.get(),
+            crop_height.get(),
+            real_min_scale,
+            step,
+            regions.required.as_ref(),
+        );
+        candidates.retain(|c| regions.allows(c));
+        #[cfg(feature = "tracing")]
+        _span.record("count", candidates.len());
+        candidates
+    };
+    let score_output = o.down_sample(SCORE_DOWN_SAMPLE as u32, monitor);
+    if candidates.is_empty() {
+        let region = match regions.required {
+            Some(ref region) if cs.required_region_fallback => region,
+            Some(_) => return Err(Error::RequiredRegionDoesNotFit),
+            None => return Err(Error::AllCropsExcluded),
+        };
 
-    edge_detect(img, &mut o);
+        let crop = containing_crop(
+            region,
+            crop_width.get() as f64 / crop_height.get() as f64,
+            width,
+            height,
+        );
+        return Ok(ScoredCrop {
+            score: score(&score_output, &crop, cs),
+            crop,
+            partial: false,
+        });
+    }
 
-    skin_detect(img, &mut o);
+    // Refinement scores at most 8 neighbours of each of the top crops on
+    // every level
+    let total = match cs.candidate_search {
+        CandidateSearch::Grid => candidates.len(),
+        CandidateSearch::CoarseToFine { coarse_step, top_k } => {
+            let levels = coarse_step.get().ilog2() as usize;
+            candidates.len() + levels * top_k.get() as usize * 8
+        }
+    };
+    let scored = Cell::new(0);
+    let score_crop = |crop: Crop| {
+        scored.set(scored.get() + 1);
+        ScoredCrop {
+            score: score(&score_output, &crop, cs),
+            crop,
+            partial: false,
+        }
+    };
+    // At least one crop is always scored, so an interrupted search still
+    // has a result
+    let interrupted = Cell::new(false);
+    let running = || {
+        let done = scored.get();
+        if done > 0 && monitor.step(AnalysisStage::Scoring, done, total).is_err() {
+            interrupted.set(true);
+        }
+        !interrupted.get()
+    };
 
-    saturation_detect(img, &mut o);
+    let _span = span!(
+        "score_candidates",
+        candidates = total,
+        scored = tracing::field::Empty,
+        score = tracing::field::Empty,
+    );
+    let candidates = candidates
+        .into_iter()
+        .take_while(|_| running())
+        .map(score_crop);
+    let top_crop = match cs.candidate_search {
+        CandidateSearch::Grid => best_crop(candidates),
+        CandidateSearch::CoarseToFine { coarse_step, top_k } => {
+            let mut seen: HashSet<(u32, u32, u32, u32)> = HashSet::new();
+            let mut top: Vec<ScoredCrop> = candidates
+                .inspect(|c| {
+                    seen.insert((c.crop.x, c.crop.y, c.crop.width, c.crop.height));
+                })
+                .collect();
+
+            let mut step = coarse_step.get();
+            while step > 1 && running() {
+                top.sort_by(|a, b| b.score.total.total_cmp(&a.score.total));
+                top.truncate(top_k.get() as usize);
+                step /= 2;
+
+                let neighbours: Vec<Crop> = top
+                    .iter()
+                    .flat_map(|c| neighbour_crops(&c.crop, step, &bounds))
+                    .filter(|c| regions.allows(c))
+                    .filter(|c| seen.insert((c.x, c.y, c.width, c.height)))
+                    .collect();
+                top.extend(
+                    neighbours
+                        .into_iter()
+                        .take_while(|_| running())
+                        .map(score_crop),
+                );
+            }
 
-    let cs: Vec<Crop> = crops(&o, crop_width.get(), crop_height.get(), real_min_scale);
-    assert!(!cs.is_empty());
-    let score_output = o.down_sample(SCORE_DOWN_SAMPLE as u32);
-    let top_crop: Option<ScoredCrop> = cs
-        .iter()
-        .map(|crop| {
-            let crop = ScoredCrop {
-                crop: crop.clone(),
-                score: score(&score_output, &crop),
-            };
-            crop
-        })
-        .fold(None, |result, scored_crop| {
-            Some(match result {
-                None => scored_crop,
-                Some(result) => {
-                    if result.score.total > scored_crop.score.total {
-                        result
-                    } else {
-                        scored_crop
-                    }
+            best_crop(top.into_iter())
+        }
+    };
+
+    let mut top_crop = top_crop.expect("At least one crop is scored");
+    #[cfg(feature = "tracing")]
+    {
+        _span.record("scored", scored.get());
+        _span.record("score", top_crop.score.total);
+    }
+    monitor.report(AnalysisStage::Scoring, 1, 1);
+    top_crop.partial = interrupted.get();
+    Ok(top_crop)
+}
+
+fn best_crop<C: Iterator<Item = ScoredCrop>>(crops: C) -> Option<ScoredCrop> {
+    crops.fold(None, |result, scored_crop| {
+        Some(match result {
+            None => scored_crop,
+            Some(result) => {
+                if result.score.total > scored_crop.score.total {
+                    result
+                } else {
+                    scored_crop
                 }
-            })
-        });
+            }
+        })
+    })
+}
+
+// Crops of the same size moved by `step` in each direction, within `bounds`
+fn neighbour_crops(crop: &Crop, step: u32, bounds: &Crop) -> Vec<Crop> {
+    let (min_x, min_y) = (bounds.x as i64, bounds.y as i64);
+    let max_x = (bounds.x + bounds.width - crop.width) as i64;
+    let max_y = (bounds.y + bounds.height - crop.height) as i64;
+    let step = step as i64;
+
+    let mut crops = vec![];
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            crops.push(Crop {
+                x: (crop.x as i64 + dx * step).clamp(min_x, max_x) as u32,
+                y: (crop.y as i64 + dy * step).clamp(min_y, max_y) as u32,
+                ..crop.clone()
+            });
+        }
+    }
+
+    crops
+}
+
+// Runs all detectors, channels are: r - skin, g - detail (edges), b - saturation
+fn feature_map<I: Image>(img: &I, cs: &CropSettings, monitor: &Monitor) -> Result<ImageMap, Error> {
+    let mut o = ImageMap::with_precision(img.width(), img.height(), cs.channel_precision);
+
+    edge_detect(img, &mut o, cs, monitor)?;
+
+    skin_detect(img, &mut o, cs, monitor)?;
+
+    saturation_detect(img, &mut o, cs, monitor)?;
+
+    if cs.sharpness_weight != 0.0 {
+        sharpness_detect(img, &mut o, cs, monitor)?;
+    }
+
+    if cs.text_weight != 0.0 || cs.text_cut_penalty != 0.0 {
+        text_detect(img, &mut o, cs, monitor)?;
+    }
 
-    top_crop.unwrap()
+    Ok(o)
 }
 
-fn edge_detect<I: Image>(i: &I, o: &mut ImageMap) {
+fn edge_detect<I: Image>(
+    i: &I,
+    o: &mut ImageMap,
+    cs: &CropSettings,
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    let _span = span!(
+        "edge_detect",
+        width = i.width(),
+        height = i.height(),
+        operator = ?cs.edge_operator
+    );
     //TODO check type casts if those are safe
 
     let w = i.width() as usize;
     let h = i.height() as usize;
-    let cies = make_cies(i);
+    let cies = make_cies(i, cs);
+    let mut plane = edge::Plane {
+        values: &cies,
+        width: w,
+        height: h,
+        border: cs.edge_border,
+    };
+    let blurred;
+    if let Some(sigma) = cs.edge_blur.filter(|&sigma| sigma > 0.0) {
+        blurred = edge::blur(&plane, sigma);
+        plane.values = &blurred;
+    }
 
     for y in 0..h {
+        monitor.step(AnalysisStage::EdgeDetection, y, h)?;
         for x in 0..w {
             let color = i.get(x as u32, y as u32);
 
-            let lightness = if x == 0 || x >= w - 1 || y == 0 || y >= h - 1 {
-                cies[y * w + x]
+            let border = x == 0 || x >= w - 1 || y == 0 || y >= h - 1;
+            let lightness = if border && cs.edge_border == EdgeBorder::Lightness {
+                plane.values[y * w + x]
+            } else {
+                plane.edge(x, y, cs.edge_operator)
+            };
+
+            let index = y * w + x;
+            o.skin[index] = color.r as f32;
+            o.detail[index] = o.quantize(lightness);
+            o.saturation[index] = color.b as f32;
+        }
+    }
+
+    monitor.report(AnalysisStage::EdgeDetection, h, h);
+    Ok(())
+}
+
+// Standard deviation of the Laplacian in a window around every pixel. Sharp
+// detail gives strong and varying responses, blur a weak and even one.
+fn sharpness_detect<I: Image>(
+    i: &I,
+    o: &mut ImageMap,
+    cs: &CropSettings,
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    let _span = span!("sharpness_detect", width = i.width(), height = i.height());
+    let w = i.width() as usize;
+    let h = i.height() as usize;
+    let cies = make_cies(i, cs);
+
+    // Summed-area tables of the Laplacian and its square, one row and column
+    // larger than the image
+    let stride = w + 1;
+    let mut sums = vec![0.0; stride * (h + 1)];
+    let mut squares = vec![0.0; stride * (h + 1)];
+    for y in 0..h {
+        monitor.step(AnalysisStage::SharpnessDetection, y, 2 * h)?;
+        let (mut row_sum, mut row_square) = (0.0, 0.0);
+        for x in 0..w {
+            let laplacian = if x == 0 || x >= w - 1 || y == 0 || y >= h - 1 {
+                0.0
             } else {
                 cies[y * w + x] * 4.0
                     - cies[x + (y - 1) * w]
@@ -384,22 +1665,82 @@ fn edge_detect<I: Image>(i: &I, o: &mut ImageMap) {
                     - cies[x + 1 + y * w]
                     - cies[x + (y + 1) * w]
             };
+            row_sum += laplacian;
+            row_square += laplacian * laplacian;
+
+            let index = (y + 1) * stride + x + 1;
+            sums[index] = sums[index - stride] + row_sum;
+            squares[index] = squares[index - stride] + row_square;
+        }
+    }
 
-            let g = bounds(lightness);
+    for y in 0..h {
+        monitor.step(AnalysisStage::SharpnessDetection, h + y, 2 * h)?;
+        let top = y.saturating_sub(SHARPNESS_RADIUS);
+        let bottom = (y + SHARPNESS_RADIUS + 1).min(h);
+        for x in 0..w {
+            let left = x.saturating_sub(SHARPNESS_RADIUS);
+            let right = (x + SHARPNESS_RADIUS + 1).min(w);
+
+            let window = |table: &[f64]| {
+                table[bottom * stride + right]
+                    - table[top * stride + right]
+                    - table[bottom * stride + left]
+                    + table[top * stride + left]
+            };
+            let n = ((bottom - top) * (right - left)) as f64;
+            let mean = window(&sums) / n;
+            let variance = (window(&squares) / n - mean * mean).max(0.0);
 
-            let nc = RGB { g: g, ..color };
-            o.set(x as u32, y as u32, nc)
+            o.sharpness[y * w + x] = o.quantize(variance.sqrt());
+        }
+    }
+
+    monitor.report(AnalysisStage::SharpnessDetection, 1, 1);
+    Ok(())
+}
+
+// Text likelihood of the rows averaged with the rows above and below, as
+// strokes of text continue vertically
+fn text_detect<I: Image>(
+    i: &I,
+    o: &mut ImageMap,
+    cs: &CropSettings,
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    let _span = span!("text_detect", width = i.width(), height = i.height());
+    let w = i.width() as usize;
+    let h = i.height() as usize;
+    // Stroke edges are thresholded on lightness from 0 to 255
+    let scale = 255.0 / cs.color_model.white();
+    let cies: Vec<f64> = make_cies(i, cs).iter().map(|l| l * scale).collect();
+
+    let mut rows = Vec::with_capacity(w * h);
+    for y in 0..h {
+        monitor.step(AnalysisStage::TextDetection, y, 2 * h)?;
+        rows.extend(text::row_likelihood(&cies[y * w..(y + 1) * w]));
+    }
+
+    for y in 0..h {
+        monitor.step(AnalysisStage::TextDetection, h + y, 2 * h)?;
+        let (top, bottom) = (y.saturating_sub(1), (y + 2).min(h));
+        for x in 0..w {
+            let sum: f64 = (top..bottom).map(|row| rows[row * w + x]).sum();
+            o.text[y * w + x] = o.quantize(sum / (bottom - top) as f64 * 255.0);
         }
     }
+
+    monitor.report(AnalysisStage::TextDetection, 1, 1);
+    Ok(())
 }
 
-fn make_cies<I: Image>(img: &I) -> Vec<f64> {
+fn make_cies<I: Image>(img: &I, cs: &CropSettings) -> Vec<f64> {
     //TODO `cies()` can probably be made RGB member that will make this function redundant
     let w = img.width();
     let h = img.height();
     let size = w as u64 * h as u64;
 
-    let size = if size > usize::max_value() as u64 {
+    let size = if size > usize::MAX as u64 {
         None
     } else {
         Some(size as usize)
@@ -411,8 +1752,7 @@ fn make_cies<I: Image>(img: &I) -> Vec<f64> {
     let mut i: usize = 0;
     for y in 0..h {
         for x in 0..w {
-            let color = img.get(x, y);
-            cies.insert(i, color.cie());
+            cies.insert(i, img.lightness(x, y, cs.color_model));
             i += 1;
         }
     }
@@ -420,10 +1760,85 @@ fn make_cies<I: Image>(img: &I) -> Vec<f64> {
     cies
 }
 
+#[cfg(test)]
 fn crops(i: &ImageMap, crop_width: u32, crop_height: u32, real_min_scale: f64) -> Vec<Crop> {
+    let bounds = Crop {
+        x: 0,
+        y: 0,
+        width: i.width,
+        height: i.height,
+    };
+    grid_crops(&bounds, crop_width, crop_height, real_min_scale, STEP, None)
+}
+
+// Smallest crop of the given aspect ratio around `region`, shrunk to the
+// image where it doesn't fit
+fn containing_crop(region: &Crop, aspect_ratio: f64, width: u32, height: u32) -> Crop {
+    let (region_width, region_height) = (region.width as f64, region.height as f64);
+    let (crop_width, crop_height) = if region_width / region_height > aspect_ratio {
+        (region_width, region_width / aspect_ratio)
+    } else {
+        (region_height * aspect_ratio, region_height)
+    };
+    let crop_width = (crop_width.round() as u32).clamp(region.width, width);
+    let crop_height = (crop_height.round() as u32).clamp(region.height, height);
+
+    let center = |start: u32, size: u32, crop_size: u32, length: u32| {
+        (start + size / 2)
+            .saturating_sub(crop_size / 2)
+            .min(length - crop_size)
+    };
+
+    Crop {
+        x: center(region.x, region.width, crop_width, width),
+        y: center(region.y, region.height, crop_height, height),
+        width: crop_width,
+        height: crop_height,
+    }
+    .shift_to_contain(region)
+}
+
+// Positions of a crop of `size` along an axis of `length` on a grid of
+// `step`. With a required span only positions covering it are returned,
+// including the outermost ones even if they are off the grid.
+fn positions(length: f64, size: f64, step: f64, required: Option<(f64, f64)>) -> Vec<f64> {
+    let stepping = (0..).map(f64::from).map(move |i| i * step);
+
+    match required {
+        None => stepping.take_while(|p| p + size <= length).collect(),
+        Some((start, end)) => {
+            let first = (end - size).max(0.0);
+            let last = start.min(length - size);
+            if first > last {
+                return vec![];
+            }
+
+            let mut positions = vec![first];
+            positions.extend(
+                stepping
+                    .skip_while(|&p| p <= first)
+                    .take_while(|&p| p < last),
+            );
+            if last > first {
+                positions.push(last);
+            }
+            positions
+        }
+    }
+}
+
+fn grid_crops(
+    bounds: &Crop,
+    crop_width: u32,
+    crop_height: u32,
+    real_min_scale: f64,
+    step: f64,
+    required_region: Option<&Crop>,
+) -> Vec<Crop> {
     let mut crops: Vec<Crop> = vec![];
-    let width = i.width as f64;
-    let height = i.height as f64;
+    let width = bounds.width as f64;
+    let height = bounds.height as f64;
+    let (left, top) = (bounds.x as f64, bounds.y as f64);
 
     let min_dimension = f64::min(width, height);
 
@@ -438,8 +1853,8 @@ fn crops(i: &ImageMap, crop_width: u32, crop_height: u32, real_min_scale: f64) -
         min_dimension
     };
 
-    let y_step = STEP.min(height);
-    let x_step = STEP.min(width);
+    let y_step = step.min(height);
+    let x_step = step.min(width);
 
     let mut scale = MAX_SCALE;
     loop {
@@ -447,13 +1862,15 @@ fn crops(i: &ImageMap, crop_width: u32, crop_height: u32, real_min_scale: f64) -
             break;
         };
 
-        let stepping = |step| (0..).map(f64::from).map(move |i| i * step);
+        let horizontal =
+            required_region.map(|r| (r.x as f64 - left, (r.x + r.width) as f64 - left));
+        let vertical = required_region.map(|r| (r.y as f64 - top, (r.y + r.height) as f64 - top));
 
-        for y in stepping(y_step).take_while(|y| y + crop_h * scale <= height) {
-            for x in stepping(x_step).take_while(|x| x + crop_w * scale <= width) {
+        for y in positions(height, crop_h * scale, y_step, vertical) {
+            for x in positions(width, crop_w * scale, x_step, horizontal) {
                 crops.push(Crop {
-                    x: x.round() as u32,
-                    y: y.round() as u32,
+                    x: (left + x).round() as u32,
+                    y: (top + y).round() as u32,
                     width: (crop_w * scale).round() as u32,
                     height: (crop_h * scale).round() as u32,
                 });
@@ -466,7 +1883,7 @@ fn crops(i: &ImageMap, crop_width: u32, crop_height: u32, real_min_scale: f64) -
     crops
 }
 
-fn score(o: &ImageMap, crop: &Crop) -> Score {
+fn score(o: &ImageMap, crop: &Crop, cs: &CropSettings) -> Score {
     let height = o.height as f64;
     let width = o.width as f64;
 
@@ -478,6 +1895,11 @@ fn score(o: &ImageMap, crop: &Crop) -> Score {
     let mut skin = 0.0;
     let mut detail = 0.0;
     let mut saturation = 0.0;
+    let mut sharpness = 0.0;
+    let mut boost = 0.0;
+    let mut text = 0.0;
+    let mut text_inside = 0.0;
+    let mut exclusion = 0.0;
 
     for y in (0..)
         .map(|i: u32| i as f64 * SCORE_DOWN_SAMPLE)
@@ -490,86 +1912,185 @@ fn score(o: &ImageMap, crop: &Crop) -> Score {
             let orig_x = (x * inv_down_sample).round() as u32;
             let orig_y = (y * inv_down_sample).round() as u32;
 
-            let color = o.get(orig_x, orig_y);
+            let i = o.index(orig_x, orig_y);
 
             let imp = importance(crop, x.round() as u32, y.round() as u32);
-            let det = color.g as f64 / 255.0;
+            let det = o.detail[i] as f64 / 255.0;
 
-            skin += color.r as f64 / 255.0 * (det + SKIN_BIAS) * imp;
+            skin += o.skin[i] as f64 / 255.0 * (det + SKIN_BIAS) * imp;
             detail += det * imp;
-            saturation += color.b as f64 / 255.0 * (det + SATURATION_BIAS) * imp;
+            saturation += o.saturation[i] as f64 / 255.0 * (det + SATURATION_BIAS) * imp;
+            sharpness += o.sharpness[i] as f64 / 255.0 * imp;
+            boost += o.boost[i] as f64 / 255.0 * imp;
+            text += o.text[i] as f64 / 255.0 * imp;
+
+            let inside = x >= crop.x as f64
+                && x < (crop.x + crop.width) as f64
+                && y >= crop.y as f64
+                && y < (crop.y + crop.height) as f64;
+            if inside {
+                exclusion += o.exclusion[i] as f64;
+                text_inside += o.text[i] as f64 / 255.0;
+            }
         }
     }
 
-    let total = (detail * DETAIL_WEIGHT + skin * SKIN_WEIGHT + saturation * SATURATION_WEIGHT)
+    let mut text_cuts = 0.0;
+    for line in &o.text_lines {
+        let inside = line.intersection_area(crop);
+        let area = line.width as u64 * line.height as u64;
+        // Counted on the grid of the other channels
+        text_cuts += inside.min(area - inside) as f64 / (down_sample * down_sample);
+    }
+    // Text near the edges has a negative importance, avoiding text must not
+    // turn that into a reward
+    let scored_text = if cs.text_weight < 0.0 {
+        text_inside
+    } else {
+        text
+    };
+
+    let total = (detail * DETAIL_WEIGHT
+        + skin * SKIN_WEIGHT
+        + saturation * SATURATION_WEIGHT
+        + sharpness * cs.sharpness_weight
+        + boost * BOOST_WEIGHT
+        + scored_text * cs.text_weight
+        - text_cuts * cs.text_cut_penalty
+        - exclusion)
         / crop.width as f64
         / crop.height as f64;
 
     Score {
-        skin: skin,
-        detail: detail,
-        saturation: saturation,
-        total: total,
+        skin,
+        detail,
+        saturation,
+        sharpness,
+        boost,
+        text,
+        text_inside,
+        text_cuts,
+        total,
     }
 }
 
-fn skin_detect<I: Image>(i: &I, o: &mut ImageMap) {
+fn skin_detect<I: Image>(
+    i: &I,
+    o: &mut ImageMap,
+    cs: &CropSettings,
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    let _span = span!(
+        "skin_detect",
+        width = i.width(),
+        height = i.height(),
+        model = ?cs.skin_model
+    );
     let w = i.width();
     let h = i.height();
+    let (brightness_min, brightness_max) = cs.color_model.skin_brightness();
 
     for y in 0..h {
+        monitor.step(AnalysisStage::SkinDetection, y as usize, h as usize)?;
         for x in 0..w {
-            let lightness = i.get(x, y).cie() / 255.0;
-            let skin = skin_col(i.get(x, y));
-
-            let nc = if skin > SKIN_THRESHOLD
-                && lightness >= SKIN_BRIGHTNESS_MIN
-                && lightness <= SKIN_BRIGHTNESS_MAX
-            {
-                let r = (skin - SKIN_THRESHOLD) * (255.0 / (1.0 - SKIN_THRESHOLD));
-                let RGB { r: _, g, b } = o.get(x, y);
+            let color = i.get(x, y);
 
-                RGB { r: bounds(r), g, b }
-            } else {
-                let RGB { r: _, g, b } = o.get(x, y);
-                RGB { r: 0, g, b }
+            let r = match cs.skin_model {
+                SkinModel::Reference => {
+                    let lightness = cs.color_model.lightness(color) / 255.0;
+                    let skin = skin_col(color);
+                    if skin > SKIN_THRESHOLD
+                        && (brightness_min..=brightness_max).contains(&lightness)
+                    {
+                        o.quantize((skin - SKIN_THRESHOLD) * (255.0 / (1.0 - SKIN_THRESHOLD)))
+                    } else {
+                        0.0
+                    }
+                }
+                // Chromaticity of nearly black pixels is mostly noise
+                _ if luma(color) < SKIN_LUMA_MIN => 0.0,
+                SkinModel::MultiTone => o.quantize(multi_tone_skin(color) * 255.0),
+                SkinModel::Chroma => o.quantize(skin_chroma(color) * 255.0),
+                SkinModel::Combined => {
+                    let skin = f64::max(multi_tone_skin(color), skin_chroma(color));
+                    o.quantize(skin * 255.0)
+                }
             };
 
-            o.set(x, y, nc);
+            let index = o.index(x, y);
+            o.skin[index] = r;
         }
     }
+
+    monitor.report(AnalysisStage::SkinDetection, 1, 1);
+    Ok(())
 }
 
-fn saturation_detect<I: Image>(i: &I, o: &mut ImageMap) {
+// Skin likelihood (0 to 1) of `SkinModel::MultiTone`
+fn multi_tone_skin(color: RGB) -> f64 {
+    let skin = skin_tone_similarity(color);
+    if skin > SKIN_TONE_THRESHOLD {
+        (skin - SKIN_TONE_THRESHOLD) / (1.0 - SKIN_TONE_THRESHOLD)
+    } else {
+        0.0
+    }
+}
+
+fn saturation_detect<I: Image>(
+    i: &I,
+    o: &mut ImageMap,
+    cs: &CropSettings,
+    monitor: &Monitor,
+) -> Result<(), Error> {
+    let _span = span!("saturation_detect", width = i.width(), height = i.height());
     let w = i.width();
     let h = i.height();
+    let (brightness_min, brightness_max) = cs.color_model.saturation_brightness();
 
     for y in 0..h {
+        monitor.step(AnalysisStage::SaturationDetection, y as usize, h as usize)?;
         for x in 0..w {
             let color = i.get(x, y);
-            let lightness = color.cie() / 255.0;
+            let lightness = cs.color_model.lightness(color) / 255.0;
             let saturation = color.saturation();
 
-            let nc = if saturation > SATURATION_THRESHOLD
-                && lightness >= SATURATION_BRIGHTNESS_MIN
-                && lightness <= SATURATION_BRIGHTNESS_MAX
+            let b = if saturation > SATURATION_THRESHOLD
+                && (brightness_min..=brightness_max).contains(&lightness)
             {
-                let b =
-                    (saturation - SATURATION_THRESHOLD) * (255.0 / (1.0 - SATURATION_THRESHOLD));
-                let RGB { r, g, b: _ } = o.get(x, y);
-                RGB { r, g, b: bounds(b) }
+                o.quantize(
+                    (saturation - SATURATION_THRESHOLD) * (255.0 / (1.0 - SATURATION_THRESHOLD)),
+                )
             } else {
-                let RGB { r, g, b: _ } = o.get(x, y);
-                RGB { r, g, b: 0 }
+                0.0
             };
 
-            o.set(x, y, nc);
+            let index = o.index(x, y);
+            o.saturation[index] = b;
         }
     }
+
+    monitor.report(AnalysisStage::SaturationDetection, 1, 1);
+    Ok(())
 }
 
+#[cfg(feature = "image")]
+mod animation;
 #[cfg(feature = "image")]
 mod image;
 
+#[cfg(feature = "image")]
+pub use self::animation::crop_gif;
+#[cfg(feature = "image")]
+pub use self::animation::{crop_animation, find_best_animation_crop};
+#[cfg(feature = "image")]
+pub use self::image::{
+    apply_orientation, load_oriented, open_oriented, thumbnail, thumbnail_encoded,
+};
+
 #[cfg(test)]
+#[allow(
+    clippy::unnecessary_cast,
+    clippy::needless_return,
+    clippy::manual_range_contains
+)]
 mod tests;
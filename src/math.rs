@@ -1,10 +1,26 @@
 use super::*;
+use std::sync::OnceLock;
 
 const SKIN_COLOR: RGB = RGB {
     r: 234,
     g: 171,
     b: 132,
 };
+// Hand-picked skin tones from light to dark, not taken from a published
+// scale
+const SKIN_TONES: [RGB; 6] = [
+    RGB::new(247, 217, 199),
+    RGB::new(234, 192, 164),
+    RGB::new(210, 161, 128),
+    RGB::new(176, 122, 90),
+    RGB::new(124, 81, 58),
+    RGB::new(74, 48, 38),
+];
+// Centre and radii of the skin region in the Cb-Cr plane
+const SKIN_CB: f64 = 110.0;
+const SKIN_CR: f64 = 148.0;
+const SKIN_CB_RADIUS: f64 = 18.0;
+const SKIN_CR_RADIUS: f64 = 18.0;
 const OUTSIDE_IMPORTANCE: f64 = -0.5;
 const EDGE_RADIUS: f64 = 0.4;
 const EDGE_WEIGHT: f64 = -20.0;
@@ -16,8 +32,42 @@ fn thirds(x: f64) -> f64 {
     f64::max(1.0 - x * x, 0.0)
 }
 
+// Linear light of every byte value, computed once
+fn srgb_table() -> &'static [f64; 256] {
+    static TABLE: OnceLock<[f64; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (v, linear) in table.iter_mut().enumerate() {
+            let v = v as f64 / 255.0;
+            *linear = if v <= 0.04045 {
+                v / 12.92
+            } else {
+                ((v + 0.055) / 1.055).powf(2.4)
+            };
+        }
+        table
+    })
+}
+
+pub fn srgb_to_linear(v: u8) -> f64 {
+    srgb_table()[v as usize]
+}
+
+// Rec. 709 luminance of the linearised channels, from 0 to 1
+pub fn linear_y(c: RGB) -> f64 {
+    0.2126 * srgb_to_linear(c.r) + 0.7152 * srgb_to_linear(c.g) + 0.0722 * srgb_to_linear(c.b)
+}
+
+pub fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 pub fn bounds(l: f64) -> u8 {
-    f64::min(f64::max(l, 0.0), 255.0).round() as u8
+    l.clamp(0.0, 255.0).round() as u8
 }
 
 pub fn skin_col(c: RGB) -> f64 {
@@ -37,6 +87,38 @@ pub fn skin_col(c: RGB) -> f64 {
     1.0 - d.min(1.0)
 }
 
+// Similarity of the chromaticity to the closest of the reference skin tones
+pub fn skin_tone_similarity(c: RGB) -> f64 {
+    let [r_norm, g_norm, b_norm] = c.normalize();
+
+    SKIN_TONES
+        .iter()
+        .map(|tone| {
+            let [r, g, b] = tone.normalize();
+            let d = ((r_norm - r).powi(2) + (g_norm - g).powi(2) + (b_norm - b).powi(2)).sqrt();
+            1.0 - d.min(1.0)
+        })
+        .fold(0.0, f64::max)
+}
+
+// 1 in the centre of the skin region of the Cb-Cr plane (BT.601, full
+// range) down to 0 on its boundary and outside
+pub fn skin_chroma(c: RGB) -> f64 {
+    let (r, g, b) = (c.r as f64, c.g as f64, c.b as f64);
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+
+    let dcb = (cb - SKIN_CB) / SKIN_CB_RADIUS;
+    let dcr = (cr - SKIN_CR) / SKIN_CR_RADIUS;
+
+    (1.0 - dcb * dcb - dcr * dcr).max(0.0)
+}
+
+// BT.601 luma from 0 to 1
+pub fn luma(c: RGB) -> f64 {
+    (0.299 * c.r as f64 + 0.587 * c.g as f64 + 0.114 * c.b as f64) / 255.0
+}
+
 pub fn importance(crop: &Crop, x: u32, y: u32) -> f64 {
     if crop.x > x || x >= crop.x + crop.width || crop.y > y || y >= crop.y + crop.height {
         return OUTSIDE_IMPORTANCE;
@@ -61,6 +143,7 @@ pub fn importance(crop: &Crop, x: u32, y: u32) -> f64 {
 }
 
 #[cfg(test)]
+#[allow(clippy::legacy_numeric_constants, clippy::manual_range_contains)]
 mod tests {
     use super::*;
     use proptest::strategy::Strategy;
@@ -98,6 +181,25 @@ mod tests {
         assert_eq!(331.49999999999994, gray(255).cie());
     }
 
+    #[test]
+    fn srgb_table_test() {
+        assert_eq!(0.0, srgb_to_linear(0));
+        assert_eq!(1.0, srgb_to_linear(255));
+        for v in 0..=255 {
+            let encoded = linear_to_srgb(srgb_to_linear(v)) * 255.0;
+            assert!((encoded - v as f64).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn luminance_test() {
+        assert!((gray(255).luminance() - 255.0).abs() < 1e-9);
+        assert!((gray(255).linear_luminance() - 255.0).abs() < 1e-9);
+        assert!((gray(100).linear_luminance() - 100.0).abs() < 1e-9);
+        // Saturated colours are brighter in linear light
+        assert!(RGB::new(255, 0, 0).linear_luminance() > RGB::new(255, 0, 0).luminance());
+    }
+
     #[test]
     fn skin_col_test() {
         assert_eq!(0.7550795306611966, skin_col(gray(0)));
@@ -146,6 +248,12 @@ mod tests {
             assert!(score >= 0.0 && score <= 0.94);
         }
 
+        #[test]
+        fn skin_models_are_between_0_and_1(c in color()) {
+            assert!((0.0..=1.0).contains(&skin_tone_similarity(c)));
+            assert!((0.0..=1.0).contains(&skin_chroma(c)));
+        }
+
         #[test]
         fn thirds_result_is_within_defined_boundaries(input in between_0_and_1()) {
             let result = thirds(input);
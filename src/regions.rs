@@ -0,0 +1,194 @@
+use super::resize::box_prescale;
+use super::{skin_detect, Crop, CropSettings, Error, Image, ImageMap, Monitor};
+
+// Shorter side of the skin map blobs are searched in
+const SKIN_MAP_SIZE: f64 = 200.0;
+// Smallest blob as a fraction of the map area
+const MIN_BLOB_AREA: f64 = 0.002;
+// Width to height ratio of the bounding box, faces are somewhat taller than
+// wide
+const MIN_BLOB_ASPECT: f64 = 0.5;
+const MAX_BLOB_ASPECT: f64 = 1.1;
+// Fraction of the bounding box covered by the blob, an ellipse covers 0.79
+const MIN_BLOB_FILL: f64 = 0.45;
+
+/// Face-like blobs of skin: connected regions of the skin channel with
+/// sensible size, shape and fill, largest first.
+///
+/// Uses the skin model of the default settings.
+pub fn detect_skin_regions<I: Image>(img: &I) -> Vec<Crop> {
+    Monitor::unmonitored(|monitor| skin_regions(img, &CropSettings::default(), monitor))
+}
+
+pub(crate) fn skin_regions<I: Image>(
+    img: &I,
+    cs: &CropSettings,
+    monitor: &Monitor,
+) -> Result<Vec<Crop>, Error> {
+    let (width, height) = (img.width(), img.height());
+    if width == 0 || height == 0 {
+        return Ok(vec![]);
+    }
+
+    let ratio = (SKIN_MAP_SIZE / f64::min(width as f64, height as f64)).min(1.0);
+    let map_width = ((width as f64 * ratio).round() as u32).max(1);
+    let map_height = ((height as f64 * ratio).round() as u32).max(1);
+    let prescaled = box_prescale(img, map_width, map_height, cs.color_model, monitor)?;
+
+    let mut o = ImageMap::with_precision(map_width, map_height, cs.channel_precision);
+    skin_detect(&prescaled, &mut o, cs, monitor)?;
+
+    let skin: Vec<bool> = o.skin.iter().map(|&s| s > 0.0).collect();
+    let min_area = (MIN_BLOB_AREA * map_width as f64 * map_height as f64).max(4.0);
+    let mut blobs: Vec<(usize, Crop)> = components(&skin, map_width, map_height)
+        .into_iter()
+        .filter(|(area, bounds)| {
+            let aspect = bounds.width as f64 / bounds.height as f64;
+            let fill = *area as f64 / (bounds.width as f64 * bounds.height as f64);
+            *area as f64 >= min_area
+                && (MIN_BLOB_ASPECT..=MAX_BLOB_ASPECT).contains(&aspect)
+                && fill >= MIN_BLOB_FILL
+        })
+        .collect();
+    blobs.sort_by_key(|b| std::cmp::Reverse(b.0));
+
+    let scale_x = width as f64 / map_width as f64;
+    let scale_y = height as f64 / map_height as f64;
+    Ok(blobs
+        .into_iter()
+        .map(|(_, blob)| {
+            let x = (blob.x as f64 * scale_x).floor() as u32;
+            let y = (blob.y as f64 * scale_y).floor() as u32;
+            let right = (((blob.x + blob.width) as f64 * scale_x).ceil() as u32).min(width);
+            let bottom = (((blob.y + blob.height) as f64 * scale_y).ceil() as u32).min(height);
+            Crop {
+                x,
+                y,
+                width: right - x,
+                height: bottom - y,
+            }
+        })
+        .collect())
+}
+
+// Pixel counts and bounding boxes of the 4-connected components of the set
+// pixels
+pub(crate) fn components(pixels: &[bool], width: u32, height: u32) -> Vec<(usize, Crop)> {
+    let (w, h) = (width as usize, height as usize);
+    let mut visited = vec![false; pixels.len()];
+    let mut stack = vec![];
+    let mut components = vec![];
+
+    for start in 0..pixels.len() {
+        if !pixels[start] || visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        stack.push(start);
+        let mut area = 0;
+        let (mut left, mut top) = (w, h);
+        let (mut right, mut bottom) = (0, 0);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % w, i / w);
+            area += 1;
+            left = left.min(x);
+            right = right.max(x);
+            top = top.min(y);
+            bottom = bottom.max(y);
+
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < w).then(|| i + 1),
+                (y > 0).then(|| i - w),
+                (y + 1 < h).then(|| i + w),
+            ];
+            for n in neighbours.iter().flatten() {
+                if pixels[*n] && !visited[*n] {
+                    visited[*n] = true;
+                    stack.push(*n);
+                }
+            }
+        }
+
+        components.push((
+            area,
+            Crop {
+                x: left as u32,
+                y: top as u32,
+                width: (right - left + 1) as u32,
+                height: (bottom - top + 1) as u32,
+            },
+        ));
+    }
+
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RgbBuffer, RGB};
+    use super::*;
+
+    const SKIN: RGB = RGB {
+        r: 255,
+        g: 200,
+        b: 159,
+    };
+
+    #[test]
+    fn finds_connected_components() {
+        #[rustfmt::skip]
+        let pixels = [
+            true,  true,  false, false,
+            false, true,  false, true,
+            false, false, false, true,
+        ];
+
+        let components = components(&pixels, 4, 3);
+
+        assert_eq!(components.len(), 2);
+        assert_eq!(
+            components[0],
+            (
+                3,
+                Crop {
+                    x: 0,
+                    y: 0,
+                    width: 2,
+                    height: 2
+                }
+            )
+        );
+        assert_eq!(components[1].0, 2);
+    }
+
+    #[test]
+    fn keeps_only_face_like_blobs() {
+        let img = RgbBuffer::from_fn(400, 300, |x, y| {
+            let (dx, dy) = (x as f64 - 100.0, y as f64 - 120.0);
+            let face = (dx / 40.0).powi(2) + (dy / 55.0).powi(2) <= 1.0;
+            // A blob wider than tall, a long thin arm, a lone pixel and a ring
+            let (wx, wy) = (x as f64 - 200.0, y as f64 - 45.0);
+            let wide = (wx / 40.0).powi(2) + (wy / 30.0).powi(2) <= 1.0;
+            let arm = (200..390).contains(&x) && (250..262).contains(&y);
+            let speck = x == 300 && y == 50;
+            let (rx, ry) = (x as f64 - 300.0, y as f64 - 120.0);
+            let radius = (rx * rx + ry * ry).sqrt();
+            let ring = (40.0..45.0).contains(&radius);
+            if face || wide || arm || speck || ring {
+                SKIN
+            } else {
+                RGB::new(30, 60, 90)
+            }
+        });
+
+        let regions = detect_skin_regions(&img);
+
+        assert_eq!(regions.len(), 1);
+        let face = &regions[0];
+        assert!(face.x <= 61 && face.x + face.width >= 139);
+        assert!(face.y <= 66 && face.y + face.height >= 174);
+        assert!(face.width <= 84 && face.height <= 114);
+    }
+}
@@ -0,0 +1,330 @@
+use super::buffer::RgbBuffer;
+use super::math::{bounds, linear_to_srgb, srgb_to_linear};
+use super::{AnalysisStage, ColorModel, Error, Image, Monitor, RGB};
+
+/// Area-average (box filter) resize of any `Image`. It is much cheaper than
+/// windowed-sinc filters and good enough for the analysis prescale.
+pub fn box_resize<I: Image>(img: &I, width: u32, height: u32) -> RgbBuffer<'static> {
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+
+    area_average(
+        img.width(),
+        img.height(),
+        width,
+        height,
+        |x, y| {
+            let RGB { r, g, b } = img.get(x, y);
+            [r as f64, g as f64, b as f64, 0.0]
+        },
+        |_, _, [r, g, b, _]| pixels.push(RGB::new(bounds(r), bounds(g), bounds(b))),
+    );
+
+    RgbBuffer::from_pixels(width, height, &pixels)
+}
+
+/// Result of the analysis prescale. Besides colors it may keep the area
+/// average of the source `Image::lightness` in one color model, so images
+/// that provide lightness natively (e.g. YUV frames) feed it to edge
+/// detection without a round trip via RGB.
+#[derive(Debug, Clone)]
+pub struct PrescaledImage {
+    pixels: RgbBuffer<'static>,
+    model: Option<ColorModel>,
+    lightness: Vec<f64>,
+}
+
+impl Image for PrescaledImage {
+    fn width(&self) -> u32 {
+        self.pixels.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.pixels.height()
+    }
+
+    fn get(&self, x: u32, y: u32) -> RGB {
+        self.pixels.get(x, y)
+    }
+
+    fn cie(&self, x: u32, y: u32) -> f64 {
+        self.lightness(x, y, ColorModel::JsCompat)
+    }
+
+    fn lightness(&self, x: u32, y: u32, model: ColorModel) -> f64 {
+        if Some(model) == self.model {
+            self.lightness[y as usize * self.width() as usize + x as usize]
+        } else {
+            model.lightness(self.get(x, y))
+        }
+    }
+}
+
+pub fn box_prescale<I: Image>(
+    img: &I,
+    width: u32,
+    height: u32,
+    model: ColorModel,
+    monitor: &Monitor,
+) -> Result<PrescaledImage, Error> {
+    prescale(img, width, height, false, Some(model), monitor)
+}
+
+/// Like `box_prescale`, but colors are averaged in linear light, so thin
+/// bright or dark lines don't blend into a too dark gray. Lightness is not
+/// kept, it is taken from the averaged colors.
+pub fn box_prescale_linear<I: Image>(
+    img: &I,
+    width: u32,
+    height: u32,
+    monitor: &Monitor,
+) -> Result<PrescaledImage, Error> {
+    prescale(img, width, height, true, None, monitor)
+}
+
+fn prescale<I: Image>(
+    img: &I,
+    width: u32,
+    height: u32,
+    linear: bool,
+    model: Option<ColorModel>,
+    monitor: &Monitor,
+) -> Result<PrescaledImage, Error> {
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    let mut lightness = Vec::with_capacity(width as usize * height as usize);
+    let mut stopped = Ok(());
+
+    area_average_rows(
+        img.width(),
+        img.height(),
+        width,
+        height,
+        |x, y| {
+            let RGB { r, g, b } = img.get(x, y);
+            let l = model.map_or(0.0, |model| img.lightness(x, y, model));
+            if linear {
+                [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), l]
+            } else {
+                [r as f64, g as f64, b as f64, l]
+            }
+        },
+        |_, _, [r, g, b, l]| {
+            let encode = |v: f64| {
+                if linear {
+                    bounds(linear_to_srgb(v) * 255.0)
+                } else {
+                    bounds(v)
+                }
+            };
+            pixels.push(RGB::new(encode(r), encode(g), encode(b)));
+            if model.is_some() {
+                lightness.push(l);
+            }
+        },
+        |y| {
+            stopped = monitor.step(AnalysisStage::Prescale, y as usize, height as usize);
+            stopped.is_ok()
+        },
+    );
+    stopped?;
+
+    Ok(PrescaledImage {
+        pixels: RgbBuffer::from_pixels(width, height, &pixels),
+        model,
+        lightness,
+    })
+}
+
+/// Generic area-average resampling over up to four channels.
+///
+/// `set` is called for every destination pixel in row-major order.
+pub fn area_average<G, S>(src_width: u32, src_height: u32, width: u32, height: u32, get: G, set: S)
+where
+    G: Fn(u32, u32) -> [f64; 4],
+    S: FnMut(u32, u32, [f64; 4]),
+{
+    area_average_rows(src_width, src_height, width, height, get, set, |_| true);
+}
+
+// Like `area_average`, but stops before the destination row for which `row`
+// returns false
+fn area_average_rows<G, S, R>(
+    src_width: u32,
+    src_height: u32,
+    width: u32,
+    height: u32,
+    get: G,
+    mut set: S,
+    mut row: R,
+) where
+    G: Fn(u32, u32) -> [f64; 4],
+    S: FnMut(u32, u32, [f64; 4]),
+    R: FnMut(u32) -> bool,
+{
+    let columns = contributions(src_width, width);
+    let rows = contributions(src_height, height);
+
+    for (y, sources) in rows.iter().enumerate() {
+        if !row(y as u32) {
+            return;
+        }
+        for (x, column) in columns.iter().enumerate() {
+            let mut sum = [0.0; 4];
+
+            for &(sy, wy) in sources {
+                for &(sx, wx) in column {
+                    let weight = wx * wy;
+                    let color = get(sx, sy);
+                    for (s, c) in sum.iter_mut().zip(color.iter()) {
+                        *s += c * weight;
+                    }
+                }
+            }
+
+            set(x as u32, y as u32, sum);
+        }
+    }
+}
+
+// For every destination index returns the source indices it covers together
+// with the covered fraction, normalized so that the weights sum up to 1.
+// An empty source covers nothing, leaving the destination black.
+fn contributions(src: u32, dst: u32) -> Vec<Vec<(u32, f64)>> {
+    if src == 0 {
+        return vec![vec![]; dst as usize];
+    }
+    let ratio = src as f64 / dst as f64;
+
+    (0..dst)
+        .map(|i| {
+            let start = i as f64 * ratio;
+            let end = ((i + 1) as f64 * ratio).min(src as f64);
+            let first = (start.floor() as u32).min(src - 1);
+            let last = (end.ceil() as u32).clamp(first + 1, src);
+
+            (first..last)
+                .map(|s| {
+                    let covered = end.min(s as f64 + 1.0) - start.max(s as f64);
+                    (s, covered.max(0.0) / (end - start))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Stripes;
+
+    impl Image for Stripes {
+        fn width(&self) -> u32 {
+            4
+        }
+
+        fn height(&self) -> u32 {
+            2
+        }
+
+        fn get(&self, x: u32, _y: u32) -> RGB {
+            if x % 2 == 1 {
+                RGB::new(255, 100, 10)
+            } else {
+                RGB::new(0, 0, 0)
+            }
+        }
+    }
+
+    #[test]
+    fn contributions_of_exact_downscale() {
+        assert_eq!(
+            contributions(4, 2),
+            vec![vec![(0, 0.5), (1, 0.5)], vec![(2, 0.5), (3, 0.5)]]
+        );
+    }
+
+    #[test]
+    fn contributions_of_fractional_downscale() {
+        assert_eq!(
+            contributions(3, 2),
+            vec![
+                vec![(0, 2.0 / 3.0), (1, 1.0 / 3.0)],
+                vec![(1, 1.0 / 3.0), (2, 2.0 / 3.0)]
+            ]
+        );
+    }
+
+    #[test]
+    fn contributions_of_upscale_point_to_single_source() {
+        assert_eq!(contributions(1, 3), vec![vec![(0, 1.0)]; 3]);
+    }
+
+    #[test]
+    fn contributions_of_empty_source() {
+        assert_eq!(contributions(0, 2), vec![vec![]; 2]);
+    }
+
+    #[test]
+    fn box_resize_averages_covered_area() {
+        let resized = box_resize(&Stripes, 2, 1);
+
+        assert_eq!(resized.width(), 2);
+        assert_eq!(resized.height(), 1);
+        assert_eq!(resized.get(0, 0), RGB::new(128, 50, 5));
+        assert_eq!(resized.get(1, 0), RGB::new(128, 50, 5));
+    }
+
+    #[test]
+    fn box_prescale_averages_lightness() {
+        let prescaled =
+            box_prescale(&Stripes, 2, 1, ColorModel::JsCompat, &Monitor::default()).unwrap();
+
+        assert_eq!(prescaled.get(0, 0), RGB::new(128, 50, 5));
+        assert_eq!(
+            prescaled.cie(0, 0),
+            (Stripes.cie(0, 0) + Stripes.cie(1, 0)) / 2.0
+        );
+
+        let model = ColorModel::Rec709;
+        let prescaled = box_prescale(&Stripes, 2, 1, model, &Monitor::default()).unwrap();
+        assert_eq!(
+            prescaled.lightness(0, 0, model),
+            (Stripes.lightness(0, 0, model) + Stripes.lightness(1, 0, model)) / 2.0
+        );
+        // Other models fall back to the averaged color
+        assert_eq!(prescaled.cie(0, 0), RGB::new(128, 50, 5).cie());
+    }
+
+    #[test]
+    fn cancelled_box_prescale_stops() {
+        let flag = std::sync::atomic::AtomicBool::new(true);
+        let monitor = Monitor {
+            cancel_flag: Some(&flag),
+            ..Monitor::default()
+        };
+
+        let result = box_prescale(&Stripes, 2, 1, ColorModel::JsCompat, &monitor);
+
+        assert_eq!(result.err(), Some(Error::Cancelled));
+    }
+
+    #[test]
+    fn box_resize_to_same_size_is_identity() {
+        let resized = box_resize(&Stripes, 4, 2);
+
+        for y in 0..2 {
+            for x in 0..4 {
+                assert_eq!(resized.get(x, y), Stripes.get(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn box_prescale_linear_averages_in_linear_light() {
+        let prescaled = box_prescale_linear(&Stripes, 2, 1, &Monitor::default()).unwrap();
+
+        // Half of full intensity is much brighter than 128 once encoded
+        assert_eq!(prescaled.get(0, 0), RGB::new(188, 71, 5));
+        assert_eq!(prescaled.cie(0, 0), RGB::new(188, 71, 5).cie());
+    }
+}
@@ -1,4 +1,7 @@
 use super::*;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 
 // All the "unobvious" numbers in tests were acquired by running same code in smartcrop.js
 // Used smartcrop.js commit: 623d271ad8faf24d78f9364fcc86b5132a368576
@@ -55,6 +58,17 @@ impl TestImage {
 }
 
 impl ImageMap {
+    fn new(width: u32, height: u32) -> ImageMap {
+        ImageMap::with_precision(width, height, ChannelPrecision::U8)
+    }
+
+    fn set(&mut self, x: u32, y: u32, color: RGB) {
+        let i = self.index(x, y);
+        self.skin[i] = color.r as f32;
+        self.detail[i] = color.g as f32;
+        self.saturation[i] = color.b as f32;
+    }
+
     fn from_image<I: Image>(image: &I) -> ImageMap {
         let mut image_map = ImageMap::new(image.width(), image.height());
 
@@ -159,6 +173,7 @@ fn score_test_image_with_single_black_pixel_then_score_is_zero() {
             width: 1,
             height: 1,
         },
+        &CropSettings::default(),
     );
 
     assert_eq!(
@@ -167,6 +182,11 @@ fn score_test_image_with_single_black_pixel_then_score_is_zero() {
             detail: 0.0,
             saturation: 0.0,
             skin: 0.0,
+            sharpness: 0.0,
+            boost: 0.0,
+            text: 0.0,
+            text_inside: 0.0,
+            text_cuts: 0.0,
             total: 0.0
         }
     );
@@ -185,12 +205,18 @@ fn score_test_image_with_single_white_pixel_then_score_is_the_same_as_for_js_ver
             width: 1,
             height: 1,
         },
+        &CropSettings::default(),
     );
 
     let js_version_score = Score {
         detail: -6.404213562373096,
         saturation: -7.685056274847715,
         skin: -6.468255697996827,
+        sharpness: 0.0,
+        boost: 0.0,
+        text: 0.0,
+        text_inside: 0.0,
+        text_cuts: 0.0,
         total: -13.692208596353678,
     };
 
@@ -204,7 +230,13 @@ fn skin_detect_single_pixel_test() {
         let mut o = ImageMap::new(1, 1);
         o.set(0, 0, color);
 
-        skin_detect(&image, &mut o);
+        skin_detect(
+            &image,
+            &mut o,
+            &CropSettings::default(),
+            &Monitor::default(),
+        )
+        .unwrap();
         o.get(0, 0)
     };
 
@@ -223,7 +255,13 @@ fn edge_detect_single_pixel_image_test() {
         let mut o = ImageMap::new(1, 1);
         o.set(0, 0, color);
 
-        edge_detect(&image, &mut o);
+        edge_detect(
+            &image,
+            &mut o,
+            &CropSettings::default(),
+            &Monitor::default(),
+        )
+        .unwrap();
 
         o.get(0, 0)
     };
@@ -249,7 +287,13 @@ fn edge_detect_3x3() {
     );
     let mut o = ImageMap::new(3, 3);
 
-    edge_detect(&image, &mut o);
+    edge_detect(
+        &image,
+        &mut o,
+        &CropSettings::default(),
+        &Monitor::default(),
+    )
+    .unwrap();
 
     assert_eq!(
         o.get(0, 0),
@@ -325,7 +369,13 @@ fn saturation_detect_3x3() {
     );
     let mut o = ImageMap::from_image(&image);
 
-    saturation_detect(&image, &mut o);
+    saturation_detect(
+        &image,
+        &mut o,
+        &CropSettings::default(),
+        &Monitor::default(),
+    )
+    .unwrap();
 
     assert_eq!(
         o.get(0, 0),
@@ -439,9 +489,1283 @@ fn down_sample_test() {
 
     let image_map = ImageMap::from_image(&image);
 
-    let result = image_map.down_sample(3);
+    let result = image_map.down_sample(3, &Monitor::default());
 
     assert_eq!(result.width, 1);
     assert_eq!(result.height, 1);
     assert_eq!(result.get(0, 0), RGB::new(184, 132, 103));
 }
+
+#[test]
+fn frame_aggregator_test() {
+    let mut first = ImageMap::new(1, 1);
+    first.set(0, 0, RGB::new(10, 200, 0));
+    let mut second = ImageMap::new(1, 1);
+    second.set(0, 0, RGB::new(20, 100, 255));
+
+    let aggregate = |aggregation| {
+        let mut aggregator = FrameAggregator::new(aggregation, ChannelPrecision::U8);
+        aggregator.add(&first);
+        aggregator.add(&second);
+        aggregator.finish().get(0, 0)
+    };
+
+    assert_eq!(aggregate(FrameAggregation::Max), RGB::new(20, 200, 255));
+    assert_eq!(aggregate(FrameAggregation::Mean), RGB::new(15, 150, 128));
+}
+
+#[test]
+fn find_best_crop_for_frames_covers_all_frames() {
+    let black = TestImage::new_from_fn(24, 8, |_, _| BLACK);
+    let skin = TestImage::new_from_fn(24, 8, |x, _| if x >= 16 { SKIN } else { BLACK });
+    let analyzer = Analyzer::new(CropSettings::default());
+    let eight = NonZeroU32::new(8).unwrap();
+
+    let crop = analyzer
+        .find_best_crop_for_frames(&[black.clone(), skin.clone()], eight, eight)
+        .unwrap()
+        .crop;
+
+    assert_eq!(
+        crop,
+        analyzer.find_best_crop(&skin, eight, eight).unwrap().crop
+    );
+    assert_ne!(
+        crop,
+        analyzer.find_best_crop(&black, eight, eight).unwrap().crop
+    );
+}
+
+#[test]
+fn find_best_crop_for_frames_errors() {
+    let analyzer = Analyzer::new(CropSettings::default());
+    let one = NonZeroU32::new(1).unwrap();
+    let frames = vec![
+        TestImage::new_single_pixel(WHITE),
+        TestImage::new_from_fn(2, 1, |_, _| WHITE),
+    ];
+
+    assert_eq!(
+        Error::NoFrames,
+        analyzer
+            .find_best_crop_for_frames(&Vec::<TestImage>::new(), one, one)
+            .unwrap_err()
+    );
+    assert_eq!(
+        Error::FrameSizeMismatch,
+        analyzer
+            .find_best_crop_for_frames(&frames, one, one)
+            .unwrap_err()
+    );
+}
+
+#[test]
+fn f32_precision_keeps_weak_features() {
+    let image = TestImage::new_single_pixel(RGB::new(1, 0, 0));
+
+    let f32_settings = CropSettings {
+        channel_precision: ChannelPrecision::F32,
+        ..CropSettings::default()
+    };
+    let u8_map = feature_map(&image, &CropSettings::default(), &Monitor::default()).unwrap();
+    let f32_map = feature_map(&image, &f32_settings, &Monitor::default()).unwrap();
+
+    assert_eq!(u8_map.detail[0], 0.0);
+    assert!((f32_map.detail[0] - 0.0722).abs() < 1e-6);
+}
+
+#[test]
+fn f32_precision_finds_faint_texture() {
+    // Texture too faint to survive rounding to 8 bits
+    let image = TestImage::new_from_fn(96, 32, |x, y| {
+        if (8..24).contains(&x) && (8..24).contains(&y) && (x + y) % 2 == 0 {
+            RGB::new(1, 0, 0)
+        } else {
+            BLACK
+        }
+    });
+    let size = NonZeroU32::new(32).unwrap();
+    let crop_with = |channel_precision| {
+        let settings = CropSettings {
+            channel_precision,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, size, size)
+            .unwrap()
+    };
+
+    let u8_crop = crop_with(ChannelPrecision::U8);
+    let f32_crop = crop_with(ChannelPrecision::F32);
+
+    assert_eq!(u8_crop.score.detail, 0.0);
+    assert!(f32_crop.score.detail > 0.0);
+    assert_eq!(f32_crop.crop.x, 0);
+
+    // Only unrounded channels tell the textured crop from an empty one
+    let totals = |channel_precision| {
+        let cs = CropSettings {
+            channel_precision,
+            ..CropSettings::default()
+        };
+        let monitor = Monitor::default();
+        let map = feature_map(&image, &cs, &monitor)
+            .unwrap()
+            .down_sample(SCORE_DOWN_SAMPLE as u32, &monitor);
+        let at = |x| Crop {
+            x,
+            y: 0,
+            width: 32,
+            height: 32,
+        };
+        (
+            score(&map, &at(0), &cs).total,
+            score(&map, &at(64), &cs).total,
+        )
+    };
+    let (u8_textured, u8_empty) = totals(ChannelPrecision::U8);
+    let (f32_textured, f32_empty) = totals(ChannelPrecision::F32);
+    assert_eq!(u8_textured, u8_empty);
+    assert!(f32_textured > f32_empty);
+}
+
+#[test]
+fn neighbour_crops_stay_within_image() {
+    let crop = Crop {
+        x: 2,
+        y: 0,
+        width: 8,
+        height: 8,
+    };
+
+    let image = Crop {
+        x: 0,
+        y: 0,
+        width: 12,
+        height: 8,
+    };
+
+    let neighbours = neighbour_crops(&crop, 4, &image);
+
+    assert_eq!(neighbours.len(), 9);
+    assert!(neighbours.iter().all(|c| c.x <= 4 && c.y == 0));
+    assert!(neighbours.iter().any(|c| c.x == 0));
+    assert!(neighbours.iter().any(|c| c.x == 4));
+}
+
+#[test]
+fn coarse_to_fine_search_positions_crop_precisely() {
+    let image = TestImage::new_from_fn(120, 40, |x, y| {
+        if (53..73).contains(&x) && (10..30).contains(&y) {
+            SKIN
+        } else {
+            BLACK
+        }
+    });
+    let size = NonZeroU32::new(40).unwrap();
+    let crop_with = |candidate_search| {
+        let settings = CropSettings {
+            candidate_search,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, size, size)
+            .unwrap()
+    };
+
+    let grid = crop_with(CandidateSearch::Grid);
+    let fine = crop_with(CandidateSearch::CoarseToFine {
+        coarse_step: NonZeroU32::new(16).unwrap(),
+        top_k: NonZeroU32::new(3).unwrap(),
+    });
+
+    assert!(fine.score.total > grid.score.total);
+    assert_ne!(fine.crop.x % 8, 0);
+}
+
+#[test]
+fn cancelled_analysis_fails_before_scoring() {
+    let image = TestImage::new_from_fn(24, 8, |x, _| if x >= 16 { SKIN } else { BLACK });
+    let eight = NonZeroU32::new(8).unwrap();
+    let flag = Arc::new(AtomicBool::new(false));
+    let analyzer = Analyzer::new(CropSettings::default()).with_cancel_flag(flag.clone());
+
+    assert!(
+        !analyzer
+            .find_best_crop(&image, eight, eight)
+            .unwrap()
+            .partial
+    );
+
+    flag.store(true, Ordering::Relaxed);
+    assert_eq!(
+        Error::Cancelled,
+        analyzer.find_best_crop(&image, eight, eight).unwrap_err()
+    );
+
+    let settings = CropSettings {
+        time_budget: Some(Duration::from_secs(0)),
+        ..CropSettings::default()
+    };
+    assert_eq!(
+        Error::Cancelled,
+        Analyzer::new(settings)
+            .find_best_crop(&image, eight, eight)
+            .unwrap_err()
+    );
+}
+
+// Counts the pixels read, to tell which stages ran
+struct CountingImage {
+    image: TestImage,
+    reads: std::cell::Cell<usize>,
+}
+
+impl Image for CountingImage {
+    fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    fn get(&self, x: u32, y: u32) -> RGB {
+        self.reads.set(self.reads.get() + 1);
+        self.image.get(x, y)
+    }
+}
+
+impl ResizableImage<TestImage> for CountingImage {
+    fn resize(&self, width: u32, height: u32) -> TestImage {
+        self.image.resize(width, height)
+    }
+}
+
+#[test]
+fn cancelled_analysis_detects_no_regions() {
+    let image = CountingImage {
+        image: TestImage::new_from_fn(24, 8, |x, _| if x >= 16 { SKIN } else { BLACK }),
+        reads: std::cell::Cell::new(0),
+    };
+    let eight = NonZeroU32::new(8).unwrap();
+    let settings = CropSettings {
+        border_tolerance: Some(BORDER_TOLERANCE),
+        skin_region_boost: 1.0,
+        ..CropSettings::default()
+    };
+    let analyzer = Analyzer::new(settings).with_cancel_flag(Arc::new(AtomicBool::new(true)));
+
+    assert_eq!(
+        Error::Cancelled,
+        analyzer.find_best_crop(&image, eight, eight).unwrap_err()
+    );
+    assert_eq!(image.reads.get(), 0);
+}
+
+#[test]
+fn cancelled_scoring_returns_partial_result() {
+    let image = TestImage::new_from_fn(24, 8, |x, _| if x >= 16 { SKIN } else { BLACK });
+    let eight = NonZeroU32::new(8).unwrap();
+    let map = feature_map(&image, &CropSettings::default(), &Monitor::default()).unwrap();
+    let flag = AtomicBool::new(true);
+    let monitor = Monitor {
+        cancel_flag: Some(&flag),
+        ..Monitor::default()
+    };
+
+    let result = analyse_map(
+        &CropSettings::default(),
+        map,
+        eight,
+        eight,
+        1.0,
+        &MapRegions::default(),
+        &monitor,
+    )
+    .unwrap();
+
+    assert!(result.partial);
+    assert_eq!(result.crop.x, 0);
+}
+
+#[test]
+fn progress_is_reported_for_every_stage() {
+    let image = TestImage::new_from_fn(600, 200, |x, _| if x >= 400 { SKIN } else { BLACK });
+    let size = NonZeroU32::new(100).unwrap();
+    let reports = Arc::new(std::sync::Mutex::new(vec![]));
+    let observer = reports.clone();
+    let analyzer = Analyzer::new(CropSettings::default())
+        .with_progress(move |stage, fraction| observer.lock().unwrap().push((stage, fraction)));
+
+    analyzer.find_best_crop(&image, size, size).unwrap();
+
+    let reports = reports.lock().unwrap();
+    let stages = [
+        AnalysisStage::Prescale,
+        AnalysisStage::EdgeDetection,
+        AnalysisStage::SkinDetection,
+        AnalysisStage::SaturationDetection,
+        AnalysisStage::DownSample,
+        AnalysisStage::Scoring,
+    ];
+    let mut previous = 0;
+    for stage in &stages {
+        let fractions: Vec<f64> = reports
+            .iter()
+            .filter(|r| r.0 == *stage)
+            .map(|r| r.1)
+            .collect();
+        let first = reports.iter().position(|r| r.0 == *stage).unwrap();
+
+        assert!(first >= previous, "{:?} reported out of order", stage);
+        assert!(fractions.len() <= 101);
+        assert!(fractions.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(fractions.last(), Some(&1.0));
+        previous = first;
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn stages_are_traced() {
+    use std::sync::Mutex;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Default)]
+    struct SpanNames(Mutex<Vec<&'static str>>);
+
+    impl Subscriber for SpanNames {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name());
+            Id::from_u64(names.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let subscriber = Arc::new(SpanNames::default());
+    let image = TestImage::new_from_fn(24, 8, |x, _| if x >= 16 { SKIN } else { BLACK });
+    let eight = NonZeroU32::new(8).unwrap();
+
+    tracing::subscriber::with_default(subscriber.clone(), || {
+        Analyzer::new(CropSettings::default())
+            .find_best_crop(&image, eight, eight)
+            .unwrap();
+    });
+
+    let names = subscriber.0.lock().unwrap();
+    assert_eq!(
+        *names,
+        vec![
+            "find_best_crop",
+            "resize",
+            "edge_detect",
+            "skin_detect",
+            "saturation_detect",
+            "generate_candidates",
+            "down_sample",
+            "score_candidates",
+        ]
+    );
+}
+
+#[test]
+fn full_resolution_analysis() {
+    let image = TestImage::new_from_fn(900, 500, |x, y| {
+        if (600..700).contains(&x) && (200..300).contains(&y) {
+            SKIN
+        } else if (x / 3 + y / 3) % 2 == 1 {
+            RGB::new(60, 90, 60)
+        } else {
+            RGB::new(90, 60, 60)
+        }
+    });
+    let crop_with = |prescale, width, height| {
+        let settings = CropSettings {
+            prescale,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(
+                &image,
+                NonZeroU32::new(width).unwrap(),
+                NonZeroU32::new(height).unwrap(),
+            )
+            .unwrap()
+            .crop
+    };
+
+    for &(width, height) in &[(1, 1), (100, 50), (30, 100), (2000, 1)] {
+        let full = crop_with(None, width, height);
+
+        assert!(full.width == 900 || full.height == 500);
+        assert!(full.x + full.width <= 900 && full.y + full.height <= 500);
+    }
+
+    let full = crop_with(None, 1, 1);
+    let prescaled = crop_with(NonZeroU32::new(400), 1, 1);
+    assert!(full.x <= 600 && full.x + full.width >= 700);
+    assert!(prescaled.x <= 600 && prescaled.x + prescaled.width >= 700);
+}
+
+#[test]
+fn prescale_size_above_image_size_is_full_resolution() {
+    let image = TestImage::new_from_fn(48, 16, |x, _| if x >= 32 { SKIN } else { BLACK });
+    let eight = NonZeroU32::new(8).unwrap();
+    let crop_with = |prescale| {
+        let settings = CropSettings {
+            prescale,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, eight, eight)
+            .unwrap()
+    };
+
+    let full = crop_with(None);
+    let prescaled = crop_with(NonZeroU32::new(400));
+
+    assert_eq!(full.crop, prescaled.crop);
+    assert_eq!(full.score, prescaled.score);
+}
+
+#[test]
+fn positions_cover_required_span() {
+    assert_eq!(positions(20.0, 8.0, 8.0, None), vec![0.0, 8.0]);
+    assert_eq!(
+        positions(40.0, 8.0, 8.0, Some((13.0, 18.0))),
+        vec![10.0, 13.0]
+    );
+    assert_eq!(
+        positions(40.0, 20.0, 8.0, Some((13.0, 18.0))),
+        vec![0.0, 8.0, 13.0]
+    );
+    assert!(positions(40.0, 8.0, 8.0, Some((10.0, 20.0))).is_empty());
+}
+
+#[test]
+fn crops_contain_required_region() {
+    // Skin on the right, but the left edge must stay in the crop
+    let image = TestImage::new_from_fn(600, 200, |x, _| if x >= 500 { SKIN } else { BLACK });
+    let size = NonZeroU32::new(100).unwrap();
+    let region = Crop {
+        x: 150,
+        y: 20,
+        width: 30,
+        height: 30,
+    };
+    let settings = CropSettings {
+        required_region: Some(region.clone()),
+        ..CropSettings::default()
+    };
+
+    let unconstrained = Analyzer::new(CropSettings::default())
+        .find_best_crop(&image, size, size)
+        .unwrap()
+        .crop;
+    let constrained = Analyzer::new(settings)
+        .find_best_crop(&image, size, size)
+        .unwrap()
+        .crop;
+
+    assert!(!unconstrained.contains(&region));
+    assert!(constrained.contains(&region));
+    // As close to the skin as the region allows
+    assert_eq!(constrained.x, 150);
+}
+
+#[test]
+fn required_region_that_does_not_fit() {
+    let image = TestImage::new_from_fn(600, 200, |x, _| if x >= 500 { SKIN } else { BLACK });
+    let size = NonZeroU32::new(100).unwrap();
+    let region = Crop {
+        x: 100,
+        y: 50,
+        width: 300,
+        height: 40,
+    };
+    let settings = CropSettings {
+        required_region: Some(region.clone()),
+        ..CropSettings::default()
+    };
+
+    assert_eq!(
+        Error::RequiredRegionDoesNotFit,
+        Analyzer::new(settings.clone())
+            .find_best_crop(&image, size, size)
+            .unwrap_err()
+    );
+
+    let fallback = Analyzer::new(CropSettings {
+        required_region_fallback: true,
+        ..settings.clone()
+    })
+    .find_best_crop(&image, size, size)
+    .unwrap()
+    .crop;
+    assert!(fallback.contains(&region));
+    assert_eq!((fallback.width, fallback.height), (300, 200));
+
+    let outside = CropSettings {
+        required_region: Some(Crop { x: 590, ..region }),
+        required_region_fallback: true,
+        ..settings
+    };
+    assert_eq!(
+        Error::RequiredRegionDoesNotFit,
+        Analyzer::new(outside)
+            .find_best_crop(&image, size, size)
+            .unwrap_err()
+    );
+}
+
+#[test]
+fn required_region_is_kept_when_prescaled() {
+    let image = TestImage::new_from_fn(1203, 801, |x, _| if x >= 1000 { SKIN } else { BLACK });
+    let size = NonZeroU32::new(100).unwrap();
+    let region = Crop {
+        x: 401,
+        y: 3,
+        width: 17,
+        height: 795,
+    };
+    let settings = CropSettings {
+        required_region: Some(region.clone()),
+        ..CropSettings::default()
+    };
+
+    let crop = Analyzer::new(settings)
+        .find_best_crop(&image, size, size)
+        .unwrap()
+        .crop;
+
+    assert!(crop.contains(&region));
+    assert!(crop.x + crop.width <= 1203 && crop.y + crop.height <= 801);
+}
+
+#[test]
+fn exclusions_move_crop_away() {
+    // Detailed text of a watermark on the left, a face on the right
+    let image = TestImage::new_from_fn(300, 100, |x, y| {
+        let text = (20..90).contains(&x) && (x / 3 + y / 3) % 2 == 0;
+        let face = (220..270).contains(&x) && (25..75).contains(&y);
+        if text || face {
+            SKIN
+        } else {
+            BLACK
+        }
+    });
+    let size = NonZeroU32::new(100).unwrap();
+    let watermark = Crop {
+        x: 20,
+        y: 0,
+        width: 70,
+        height: 100,
+    };
+    let find = |exclusions| {
+        let settings = CropSettings {
+            exclusions,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, size, size)
+            .unwrap()
+    };
+
+    let unrestricted = find(vec![]).crop;
+    let soft = find(vec![Exclusion {
+        region: watermark.clone(),
+        weight: 10.0,
+        hard: false,
+    }]);
+    let hard = find(vec![Exclusion {
+        region: watermark.clone(),
+        weight: 0.0,
+        hard: true,
+    }]);
+
+    assert!(unrestricted.intersects(&watermark));
+    assert!(soft.crop.x >= 170);
+    assert!(!hard.crop.intersects(&watermark));
+}
+
+#[test]
+fn crops_are_shifted_off_hard_exclusions() {
+    let bounds = Crop {
+        x: 0,
+        y: 0,
+        width: 300,
+        height: 100,
+    };
+    let exclusion = Crop {
+        x: 150,
+        y: 0,
+        width: 20,
+        height: 100,
+    };
+    let crop = |x| Crop {
+        x,
+        y: 0,
+        width: 100,
+        height: 100,
+    };
+
+    // One pixel over the left or the right edge of the exclusion
+    assert_eq!(
+        crop(51).shift_off(&[&exclusion], &bounds, None),
+        Some(crop(50))
+    );
+    assert_eq!(
+        crop(169).shift_off(&[&exclusion], &bounds, None),
+        Some(crop(170))
+    );
+    assert_eq!(
+        crop(20).shift_off(&[&exclusion], &bounds, None),
+        Some(crop(20))
+    );
+    // The shift must keep the required region and stay inside the bounds
+    let required = Crop {
+        x: 140,
+        y: 40,
+        width: 5,
+        height: 5,
+    };
+    assert_eq!(
+        crop(60).shift_off(&[&exclusion], &bounds, Some(&required)),
+        Some(crop(50))
+    );
+    assert_eq!(
+        crop(51).shift_off(
+            &[&exclusion],
+            &bounds,
+            Some(&Crop {
+                x: 145,
+                width: 30,
+                ..required
+            })
+        ),
+        None
+    );
+}
+
+#[test]
+fn all_crops_excluded() {
+    let image = TestImage::new_from_fn(200, 100, |_, _| SKIN);
+    let size = NonZeroU32::new(100).unwrap();
+    let settings = CropSettings {
+        exclusions: vec![Exclusion {
+            region: Crop {
+                x: 90,
+                y: 40,
+                width: 20,
+                height: 20,
+            },
+            weight: 1.0,
+            hard: true,
+        }],
+        ..CropSettings::default()
+    };
+
+    let result = Analyzer::new(settings).find_best_crop(&image, size, size);
+
+    assert_eq!(result.unwrap_err(), Error::AllCropsExcluded);
+}
+
+#[test]
+fn crops_stay_inside_borders() {
+    // Pillarbox bars, the content is a flat area with a face on the left
+    let image = TestImage::new_from_fn(300, 100, |x, y| {
+        if !(50..250).contains(&x) {
+            BLACK
+        } else if (60..90).contains(&x) && (30..70).contains(&y) {
+            SKIN
+        } else {
+            RGB::new(60, 90, 60)
+        }
+    });
+    let size = NonZeroU32::new(100).unwrap();
+    let settings = CropSettings {
+        border_tolerance: Some(BORDER_TOLERANCE),
+        ..CropSettings::default()
+    };
+
+    let crop = Analyzer::new(settings)
+        .find_best_crop(&image, size, size)
+        .unwrap()
+        .crop;
+
+    assert!(crop.x >= 50 && crop.x + crop.width <= 250);
+    assert!(crop.contains(&Crop {
+        x: 60,
+        y: 30,
+        width: 30,
+        height: 40,
+    }));
+}
+
+#[test]
+fn sharpness_is_higher_in_focus() {
+    // Fine checkerboard on the left, the same contrast blurred into a smooth
+    // ramp on the right
+    let image = TestImage::new_from_fn(64, 16, |x, y| {
+        if x < 32 {
+            if (x / 2 + y / 2) % 2 == 0 {
+                WHITE
+            } else {
+                BLACK
+            }
+        } else {
+            let v = (x - 32) as u8 * 8;
+            RGB::new(v, v, v)
+        }
+    });
+    let settings = CropSettings {
+        sharpness_weight: 1.0,
+        ..CropSettings::default()
+    };
+
+    let map = feature_map(&image, &settings, &Monitor::default()).unwrap();
+
+    assert!(map.sharpness[map.index(16, 8)] > 100.0);
+    assert!(map.sharpness[map.index(48, 8)] < 10.0);
+}
+
+#[test]
+fn sharpness_weight_prefers_crops_in_focus() {
+    // Out of focus face on the left, sharp object on the right
+    let image = TestImage::new_from_fn(300, 100, |x, y| {
+        let (dx, dy) = (x as f64 - 75.0, y as f64 - 50.0);
+        let face = ((40.0 - (dx * dx + dy * dy).sqrt()) / 15.0).clamp(0.0, 1.0);
+        let object = (205..245).contains(&x) && (30..70).contains(&y);
+        if object && (x / 2 + y / 2) % 2 == 0 {
+            RGB::new(120, 120, 120)
+        } else if object {
+            RGB::new(30, 30, 30)
+        } else {
+            // Faint sensor noise
+            let noise = if (x + y) % 2 == 0 { 5.0 } else { -5.0 };
+            let blend = |skin: f64| (skin * face + 30.0 * (1.0 - face) + noise) as u8;
+            RGB::new(blend(255.0), blend(200.0), blend(159.0))
+        }
+    });
+    let size = NonZeroU32::new(100).unwrap();
+    let find = |sharpness_weight| {
+        let settings = CropSettings {
+            sharpness_weight,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, size, size)
+            .unwrap()
+    };
+
+    let without = find(0.0);
+    let with = find(1.0);
+
+    assert!(without.crop.x + without.crop.width <= 150);
+    assert_eq!(without.score.sharpness, 0.0);
+    assert!(with.crop.x >= 150);
+}
+
+#[test]
+fn edge_border_handling() {
+    let image = TestImage::new_from_fn(5, 5, |_, _| RGB::new(100, 100, 100));
+    let detect = |edge_operator, edge_border| {
+        let settings = CropSettings {
+            edge_operator,
+            edge_border,
+            edge_blur: Some(1.0),
+            ..CropSettings::default()
+        };
+        let mut o = ImageMap::new(5, 5);
+        edge_detect(&image, &mut o, &settings, &Monitor::default()).unwrap();
+        o
+    };
+
+    let legacy = detect(EdgeOperator::Laplacian, EdgeBorder::Lightness);
+    assert_eq!(legacy.detail[0], 130.0);
+    assert_eq!(legacy.detail[12], 0.0);
+
+    for &operator in &[
+        EdgeOperator::Laplacian,
+        EdgeOperator::Sobel,
+        EdgeOperator::Scharr,
+    ] {
+        for &border in &[EdgeBorder::Clamp, EdgeBorder::Reflect] {
+            assert!(detect(operator, border).detail.iter().all(|&d| d == 0.0));
+        }
+    }
+}
+
+// Swatches of the ten tones of the Monk Skin Tone scale (skintone.google),
+// in several shades and with the hue shifted towards red and yellow
+fn monk_swatches() -> Vec<Vec<RGB>> {
+    let tones = [
+        (246, 237, 228),
+        (243, 231, 219),
+        (247, 234, 208),
+        (234, 218, 186),
+        (215, 189, 150),
+        (160, 126, 86),
+        (130, 92, 67),
+        (96, 65, 52),
+        (58, 49, 42),
+        (41, 36, 32),
+    ];
+
+    tones
+        .iter()
+        .map(|&(r, g, b)| {
+            let mut swatches = vec![];
+            for &shade in &[0.85, 1.0, 1.05] {
+                for &(dg, db) in &[(0.0, 0.0), (-0.04, -0.02), (0.02, -0.05)] {
+                    let channel = |v: u8, shift: f64| (v as f64 * shade * (1.0 + shift)).min(255.0);
+                    swatches.push(RGB::new(
+                        channel(r, 0.0) as u8,
+                        channel(g, dg) as u8,
+                        channel(b, db) as u8,
+                    ));
+                }
+            }
+            swatches
+        })
+        .collect()
+}
+
+fn skin_detection_rate(skin_model: SkinModel, colors: &[RGB]) -> f64 {
+    let image = TestImage::new_from_fn(colors.len() as u32, 1, |x, _| colors[x as usize]);
+    let settings = CropSettings {
+        skin_model,
+        ..CropSettings::default()
+    };
+    let mut o = ImageMap::new(image.width(), 1);
+    skin_detect(&image, &mut o, &settings, &Monitor::default()).unwrap();
+
+    o.skin.iter().filter(|&&s| s > 0.0).count() as f64 / colors.len() as f64
+}
+
+#[test]
+fn skin_models_detect_all_skin_types() {
+    let not_skin = [
+        RGB::new(135, 206, 235),
+        RGB::new(60, 140, 60),
+        RGB::new(128, 128, 128),
+        RGB::new(255, 255, 255),
+        RGB::new(245, 245, 220),
+        RGB::new(200, 30, 30),
+        RGB::new(255, 165, 0),
+        RGB::new(30, 60, 200),
+    ];
+    // Hues close to skin: oranges, terracotta, brick and teak wood
+    let near_skin = [
+        RGB::new(255, 140, 0),
+        RGB::new(255, 165, 79),
+        RGB::new(226, 114, 91),
+        RGB::new(178, 34, 34),
+        RGB::new(150, 111, 51),
+    ];
+    // Oak wood, sand and khaki, MultiTone only
+    let pale_near_skin = [
+        RGB::new(193, 154, 107),
+        RGB::new(194, 178, 128),
+        RGB::new(195, 176, 145),
+    ];
+    let rates = |model| -> Vec<f64> {
+        monk_swatches()
+            .iter()
+            .map(|swatches| skin_detection_rate(model, swatches))
+            .collect()
+    };
+
+    // Smallest detection rate of every tone, the gaps are the ones stated
+    // in the docs of `SkinModel`
+    #[rustfmt::skip]
+    let minimum_rates = [
+        (SkinModel::Reference, [0.0, 0.2, 0.3, 0.3, 1.0, 1.0, 1.0, 1.0, 0.6, 0.0]),
+        (SkinModel::MultiTone, [0.0, 0.3, 0.6, 0.6, 0.6, 0.3, 1.0, 1.0, 1.0, 0.85]),
+        (SkinModel::Chroma,    [0.6, 0.85, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0]),
+        (SkinModel::Combined,  [0.6, 0.85, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.85]),
+    ];
+    for (model, minimum) in &minimum_rates {
+        for (tone, (rate, minimum)) in rates(*model).iter().zip(minimum).enumerate() {
+            assert!(
+                rate >= minimum,
+                "{:?} detects tone {} {}",
+                model,
+                tone + 1,
+                rate
+            );
+        }
+    }
+
+    // The single reference colour misses the lightest and darkest tones
+    let reference = rates(SkinModel::Reference);
+    assert!(reference[0] < 0.9 && reference[9] < 0.9);
+
+    for &model in &[SkinModel::MultiTone, SkinModel::Chroma, SkinModel::Combined] {
+        assert_eq!(skin_detection_rate(model, &not_skin), 0.0);
+        assert_eq!(skin_detection_rate(model, &near_skin), 0.0);
+    }
+    assert_eq!(
+        skin_detection_rate(SkinModel::MultiTone, &pale_near_skin),
+        0.0
+    );
+}
+
+#[test]
+fn color_models() {
+    let image = TestImage::new_single_pixel(RGB::new(150, 150, 150));
+    let detail = |color_model| {
+        let settings = CropSettings {
+            color_model,
+            ..CropSettings::default()
+        };
+        let mut o = ImageMap::new(1, 1);
+        edge_detect(&image, &mut o, &settings, &Monitor::default()).unwrap();
+        o.detail[0]
+    };
+
+    assert_eq!(detail(ColorModel::JsCompat), 195.0);
+    assert_eq!(detail(ColorModel::Rec709), 150.0);
+    assert_eq!(detail(ColorModel::LinearRec709), 150.0);
+}
+
+#[test]
+fn rec709_brightness_limits_accept_the_same_neutral_tones() {
+    let accepted = |model: ColorModel, (min, max): (f64, f64)| -> Vec<bool> {
+        (0..=255)
+            .map(|v| (min..=max).contains(&(model.lightness(RGB::new(v, v, v)) / 255.0)))
+            .collect()
+    };
+    let differences = |legacy: Vec<bool>, corrected: Vec<bool>| {
+        legacy
+            .iter()
+            .zip(&corrected)
+            .filter(|(a, b)| a != b)
+            .count()
+    };
+
+    for &model in &[ColorModel::Rec709, ColorModel::LinearRec709] {
+        let skin = differences(
+            accepted(ColorModel::JsCompat, ColorModel::JsCompat.skin_brightness()),
+            accepted(model, model.skin_brightness()),
+        );
+        let saturation = differences(
+            accepted(
+                ColorModel::JsCompat,
+                ColorModel::JsCompat.saturation_brightness(),
+            ),
+            accepted(model, model.saturation_brightness()),
+        );
+
+        assert!(skin <= 2, "{:?}", model);
+        assert!(saturation <= 2, "{:?}", model);
+    }
+}
+
+#[test]
+fn linear_light_only_changes_the_prescale() {
+    // Thin bright lines, which blend into a darker gray averaged as encoded
+    let image = TestImage::new_from_fn(64, 64, |x, _| if x % 4 == 0 { WHITE } else { BLACK });
+    let monitor = Monitor::default();
+    let settings = CropSettings {
+        linear_light: true,
+        ..CropSettings::default()
+    };
+
+    let encoded = resize::box_prescale(&image, 16, 16, settings.color_model, &monitor).unwrap();
+    let linear = resize::box_prescale_linear(&image, 16, 16, &monitor).unwrap();
+    assert_eq!(encoded.get(0, 0), RGB::new(64, 64, 64));
+    assert_eq!(linear.get(0, 0), RGB::new(137, 137, 137));
+
+    // Edges are detected on the re-encoded colours in the colour model
+    let mut o = ImageMap::new(16, 16);
+    edge_detect(&linear, &mut o, &settings, &monitor).unwrap();
+    let flat = TestImage::new_from_fn(16, 16, |_, _| RGB::new(137, 137, 137));
+    let mut expected = ImageMap::new(16, 16);
+    edge_detect(&flat, &mut expected, &CropSettings::default(), &monitor).unwrap();
+    assert_eq!(o.detail, expected.detail);
+}
+
+#[test]
+fn boosts_attract_crops() {
+    let image = TestImage::new_from_fn(300, 100, |x, _| if x < 100 { SKIN } else { BLACK });
+    let size = NonZeroU32::new(100).unwrap();
+    let region = Crop {
+        x: 220,
+        y: 30,
+        width: 40,
+        height: 40,
+    };
+    let settings = CropSettings {
+        boosts: vec![Boost {
+            region: region.clone(),
+            weight: 1.0,
+        }],
+        ..CropSettings::default()
+    };
+
+    let crop = Analyzer::new(settings)
+        .find_best_crop(&image, size, size)
+        .unwrap();
+
+    assert!(crop.crop.contains(&region));
+    assert!(crop.score.boost > 0.0);
+}
+
+// Finds the brightest column of the image as a 40px wide region
+struct BrightColumnDetector;
+
+impl RegionDetector for BrightColumnDetector {
+    fn detect(&self, img: &dyn Image) -> Vec<(Crop, f64)> {
+        let brightness = |x| {
+            (0..img.height())
+                .map(|y| img.get(x, y).g as u32)
+                .sum::<u32>()
+        };
+        let x = (0..img.width()).max_by_key(|&x| brightness(x)).unwrap();
+        let region = Crop {
+            x: x.saturating_sub(20),
+            y: 0,
+            width: 40,
+            height: img.height(),
+        };
+        vec![(region.clone(), 2.0), (Crop { width: 0, ..region }, 1.0)]
+    }
+}
+
+#[test]
+fn detected_regions_are_boosted() {
+    let image = TestImage::new_from_fn(300, 100, |x, _| match x {
+        0..=99 => SKIN,
+        240 => GREEN,
+        _ => BLACK,
+    });
+    let size = NonZeroU32::new(100).unwrap();
+
+    let crop = Analyzer::new(CropSettings::default())
+        .with_detector(BrightColumnDetector)
+        .find_best_crop(&image, size, size)
+        .unwrap();
+
+    assert!(crop.crop.x > 140 && crop.crop.x <= 220);
+    assert!(crop.score.boost > 0.0);
+}
+
+// Cancels the analysis it runs in
+struct CancellingDetector {
+    flag: Arc<AtomicBool>,
+    calls: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl RegionDetector for CancellingDetector {
+    fn detect(&self, _img: &dyn Image) -> Vec<(Crop, f64)> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.flag.store(true, Ordering::Relaxed);
+        vec![]
+    }
+}
+
+#[test]
+fn detectors_run_under_the_monitor() {
+    let image = TestImage::new_from_fn(300, 100, |x, _| if x == 240 { GREEN } else { BLACK });
+    let size = NonZeroU32::new(100).unwrap();
+    let reports = Arc::new(std::sync::Mutex::new(vec![]));
+    let observer = reports.clone();
+    Analyzer::new(CropSettings::default())
+        .with_detector(BrightColumnDetector)
+        .with_progress(move |stage, fraction| observer.lock().unwrap().push((stage, fraction)))
+        .find_best_crop(&image, size, size)
+        .unwrap();
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports[0], (AnalysisStage::RegionDetection, 0.0));
+    let last = reports
+        .iter()
+        .rposition(|r| r.0 == AnalysisStage::RegionDetection)
+        .unwrap();
+    assert_eq!(reports[last].1, 1.0);
+    assert!(reports[..last]
+        .iter()
+        .all(|r| r.0 == AnalysisStage::RegionDetection));
+
+    let flag = Arc::new(AtomicBool::new(false));
+    let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let detector = || CancellingDetector {
+        flag: flag.clone(),
+        calls: calls.clone(),
+    };
+    let result = Analyzer::new(CropSettings::default())
+        .with_cancel_flag(flag.clone())
+        .with_detector(detector())
+        .with_detector(detector())
+        .find_best_crop(&image, size, size);
+
+    assert_eq!(result.unwrap_err(), Error::Cancelled);
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn skin_regions_are_boosted() {
+    // Face on the left, detailed texture on the right
+    let image = TestImage::new_from_fn(300, 100, |x, y| {
+        let (dx, dy) = (x as f64 - 50.0, y as f64 - 50.0);
+        if (dx / 20.0).powi(2) + (dy / 28.0).powi(2) <= 1.0 {
+            SKIN
+        } else if x >= 150 && (x / 2 + y / 2) % 2 == 0 {
+            RED
+        } else if x >= 150 {
+            BLUE
+        } else {
+            BLACK
+        }
+    });
+    let size = NonZeroU32::new(100).unwrap();
+    let find = |skin_region_boost| {
+        let settings = CropSettings {
+            skin_region_boost,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, size, size)
+            .unwrap()
+            .crop
+    };
+
+    // The face spans 30 to 70 horizontally
+    assert!(find(0.0).x > 70);
+    assert!(find(0.5).x <= 30);
+}
+
+// Rows of dark 2px strokes with gaps like between letters and words inside
+// `line`, light grey elsewhere
+fn text_pixel(line: &Crop, x: u32, y: u32) -> Option<RGB> {
+    let inside = x >= line.x && x < line.x + line.width && y >= line.y && y < line.y + line.height;
+    if !inside {
+        return None;
+    }
+    let gaps = [3, 4, 3, 7];
+    let mut position = x - line.x;
+    for gap in gaps.iter().cycle() {
+        if position < 2 {
+            return Some(RGB::new(30, 30, 30));
+        }
+        if position < 2 + gap {
+            return Some(RGB::new(220, 220, 220));
+        }
+        position -= 2 + gap;
+    }
+    unreachable!()
+}
+
+#[test]
+fn text_weight_keeps_or_avoids_text() {
+    let line = Crop {
+        x: 120,
+        y: 40,
+        width: 60,
+        height: 14,
+    };
+    let image = TestImage::new_from_fn(300, 100, |x, y| {
+        text_pixel(&line, x, y).unwrap_or(RGB::new(220, 220, 220))
+    });
+    let size = NonZeroU32::new(100).unwrap();
+    let find = |text_weight| {
+        let settings = CropSettings {
+            text_weight,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, size, size)
+            .unwrap()
+    };
+
+    let keeping = find(1.0);
+    let avoiding = find(-1.0);
+
+    assert!(keeping.crop.contains(&line));
+    assert!(keeping.score.text > 0.0);
+    assert!(keeping.score.text_inside > 0.0);
+    assert!(!avoiding.crop.intersects(&line));
+    assert_eq!(avoiding.score.text_inside, 0.0);
+}
+
+#[test]
+fn text_is_detected_alike_in_every_color_model() {
+    let line = Crop {
+        x: 20,
+        y: 10,
+        width: 60,
+        height: 14,
+    };
+    // Faint strokes stay below the stroke edge and dark ones above it,
+    // whatever scale the color model measures lightness on
+    let strokes = |gray| {
+        TestImage::new_from_fn(100, 40, |x, y| match text_pixel(&line, x, y) {
+            Some(color) if color.r < 128 => RGB::new(gray, gray, gray),
+            _ => RGB::new(220, 220, 220),
+        })
+    };
+    let text = |image: &TestImage, color_model| {
+        let settings = CropSettings {
+            color_model,
+            text_weight: 1.0,
+            ..CropSettings::default()
+        };
+        let mut o = ImageMap::new(100, 40);
+        text_detect(image, &mut o, &settings, &Monitor::default()).unwrap();
+        o.text
+    };
+
+    let faint = strokes(185);
+    assert!(text(&faint, ColorModel::JsCompat).iter().all(|&t| t == 0.0));
+    assert!(text(&faint, ColorModel::Rec709).iter().all(|&t| t == 0.0));
+    let dark = strokes(30);
+    assert_eq!(
+        text(&dark, ColorModel::JsCompat),
+        text(&dark, ColorModel::Rec709)
+    );
+    assert!(text(&dark, ColorModel::Rec709).iter().any(|&t| t > 0.0));
+}
+
+#[test]
+fn crops_do_not_cut_through_text_lines() {
+    let line = Crop {
+        x: 150,
+        y: 40,
+        width: 70,
+        height: 14,
+    };
+    // Skin pulls the crop to the right end, where it would cut the line
+    let image = TestImage::new_from_fn(300, 100, |x, y| match text_pixel(&line, x, y) {
+        Some(color) => color,
+        None if x >= 220 => SKIN,
+        None => RGB::new(220, 220, 220),
+    });
+    let size = NonZeroU32::new(100).unwrap();
+    let find = |text_cut_penalty| {
+        let settings = CropSettings {
+            text_cut_penalty,
+            ..CropSettings::default()
+        };
+        Analyzer::new(settings)
+            .find_best_crop(&image, size, size)
+            .unwrap()
+    };
+
+    let cutting = find(0.0);
+    let keeping = find(1.0);
+
+    assert!(cutting.crop.intersects(&line) && !cutting.crop.contains(&line));
+    assert!(keeping.crop.contains(&line));
+    assert_eq!(keeping.score.text_cuts, 0.0);
+}
@@ -0,0 +1,162 @@
+use super::regions::components;
+use super::Crop;
+
+// Lightness step, from 0 to 255, counted as an edge of a stroke
+const STROKE_EDGE: f64 = 40.0;
+// Widest run between two edges of opposite direction counted as a stroke
+const MAX_STROKE_WIDTH: usize = 6;
+// Half width of the horizontal window strokes are counted in
+const TEXT_RADIUS: usize = 8;
+// Strokes in the window giving full likelihood
+const MIN_STROKES: f64 = 3.0;
+// Text likelihood (0 to 255) of the pixels of text lines
+const TEXT_LINE_THRESHOLD: f32 = 128.0;
+
+// Run of pixels between two edges of opposite direction
+struct Run {
+    start: usize,
+    end: usize,
+    dark: bool,
+}
+
+// Likelihood (0 to 1) that the pixels of a row of lightness values are part
+// of text. Text crosses rows as several narrow strokes of a similar width,
+// all darker or all lighter than the background.
+pub(crate) fn row_likelihood(row: &[f64]) -> Vec<f64> {
+    let mut runs = vec![];
+    let mut last_edge: Option<(usize, bool)> = None;
+    for x in 1..row.len() {
+        let step = row[x] - row[x - 1];
+        if step.abs() < STROKE_EDGE {
+            continue;
+        }
+        let rising = step > 0.0;
+        if let Some((start, previous)) = last_edge {
+            if previous != rising {
+                runs.push(Run {
+                    start,
+                    end: x,
+                    dark: !previous,
+                });
+            }
+        }
+        last_edge = Some((x, rising));
+    }
+
+    let mut first = 0;
+    (0..row.len())
+        .map(|x| {
+            let (left, right) = (x.saturating_sub(TEXT_RADIUS), x + TEXT_RADIUS + 1);
+            while first < runs.len() && runs[first].start < left {
+                first += 1;
+            }
+            let strokes = |dark: bool| -> Vec<f64> {
+                runs[first..]
+                    .iter()
+                    .take_while(|r| r.end <= right)
+                    .filter(|r| r.dark == dark && r.end - r.start <= MAX_STROKE_WIDTH)
+                    .map(|r| (r.end - r.start) as f64)
+                    .collect()
+            };
+            let (dark, light) = (strokes(true), strokes(false));
+            let widths = if dark.len() >= light.len() {
+                dark
+            } else {
+                light
+            };
+            if widths.is_empty() {
+                return 0.0;
+            }
+
+            let n = widths.len() as f64;
+            let mean = widths.iter().sum::<f64>() / n;
+            let variance = widths.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n;
+            let consistency = (1.0 - variance.sqrt() / mean).max(0.0);
+            (n / MIN_STROKES).min(1.0) * consistency
+        })
+        .collect()
+}
+
+// Bounding boxes of the lines of likely text in the text plane
+pub(crate) fn text_lines(text: &[f32], width: u32, height: u32) -> Vec<Crop> {
+    let pixels: Vec<bool> = text.iter().map(|&t| t >= TEXT_LINE_THRESHOLD).collect();
+    components(&pixels, width, height)
+        .into_iter()
+        .map(|(_, bounds)| bounds)
+        .filter(|b| b.width >= b.height && b.width as usize > 2 * TEXT_RADIUS)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Dark strokes of width 2 on a light background, with gaps like between
+    // letters and words
+    fn text_row() -> Vec<f64> {
+        let gaps = [3, 4, 3, 7, 3, 4, 3];
+        let mut row = vec![220.0; 10];
+        for gap in gaps.iter() {
+            row.extend_from_slice(&[30.0, 30.0]);
+            row.extend(std::iter::repeat_n(220.0, *gap));
+        }
+        row.extend(std::iter::repeat_n(220.0, 20));
+        row
+    }
+
+    #[test]
+    fn strokes_of_similar_width_are_likely_text() {
+        let row = text_row();
+
+        let likelihood = row_likelihood(&row);
+
+        assert_eq!(likelihood[20], 1.0);
+        assert!(likelihood[14..49].iter().all(|&l| l > 0.5));
+        assert_eq!(likelihood[0], 0.0);
+        assert_eq!(likelihood[row.len() - 1], 0.0);
+    }
+
+    #[test]
+    fn flat_areas_steps_and_irregular_runs_are_not_text() {
+        let flat = vec![128.0; 40];
+        let step: Vec<f64> = (0..40).map(|x| if x < 20 { 0.0 } else { 255.0 }).collect();
+        // Dark and light runs of very different widths
+        let widths = [(1, 5), (6, 1), (1, 6), (5, 2), (1, 6), (6, 1)];
+        let mut irregular = vec![];
+        for (dark, light) in widths.iter() {
+            irregular.extend(std::iter::repeat_n(20.0, *dark));
+            irregular.extend(std::iter::repeat_n(200.0, *light));
+        }
+
+        assert!(row_likelihood(&flat).iter().all(|&l| l == 0.0));
+        assert!(row_likelihood(&step).iter().all(|&l| l == 0.0));
+        assert!(row_likelihood(&irregular).iter().all(|&l| l < 0.5));
+    }
+
+    #[test]
+    fn only_wide_blocks_are_text_lines() {
+        let (width, height) = (60, 30);
+        let text: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let line = (5..45).contains(&x) && (4..8).contains(&y);
+                let column = (50..54).contains(&x) && (10..28).contains(&y);
+                if line || column {
+                    255.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+        assert_eq!(
+            text_lines(&text, width, height),
+            vec![Crop {
+                x: 5,
+                y: 4,
+                width: 40,
+                height: 4,
+            }]
+        );
+    }
+}
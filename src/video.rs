@@ -0,0 +1,378 @@
+use super::{Analyzer, Crop, CropSettings, Error, Image, ImageMap, Monitor, ResizableImage};
+use std::num::NonZeroU32;
+
+// Longer side of the feature map used to detect scene cuts
+const SIGNATURE_SIZE: f64 = 32.0;
+
+#[derive(Clone, Debug)]
+pub struct VideoCropSettings {
+    /// Full analysis runs on every n-th frame and on scene cuts, frames in
+    /// between keep following the last target
+    pub keyframe_interval: NonZeroU32,
+    /// Target moves smaller than this fraction of the crop size are ignored
+    pub dead_zone: f64,
+    /// Fraction of the remaining distance to the target covered per frame
+    pub easing: f64,
+    /// Maximal move per frame as a fraction of the crop size
+    pub max_pan_speed: f64,
+    /// Mean difference (0 to 1) of the feature channels of consecutive frames
+    /// above which the crop jumps to the new target instead of panning
+    pub scene_cut_threshold: f64,
+}
+
+impl Default for VideoCropSettings {
+    fn default() -> VideoCropSettings {
+        VideoCropSettings {
+            keyframe_interval: NonZeroU32::new(10).unwrap(),
+            dead_zone: 0.1,
+            easing: 0.2,
+            max_pan_speed: 0.02,
+            scene_cut_threshold: 0.15,
+        }
+    }
+}
+
+/// Produces a temporally stable crop for every frame of a video.
+pub struct VideoCropper {
+    analyzer: Analyzer,
+    settings: VideoCropSettings,
+    width: NonZeroU32,
+    height: NonZeroU32,
+
+    frame_index: u64,
+    signature: Option<ImageMap>,
+    target: Option<Crop>,
+    position: Option<(f64, f64)>,
+    panning: bool,
+}
+
+impl VideoCropper {
+    pub fn new(
+        crop_settings: CropSettings,
+        settings: VideoCropSettings,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> VideoCropper {
+        VideoCropper {
+            analyzer: Analyzer::new(crop_settings),
+            settings,
+            width,
+            height,
+            frame_index: 0,
+            signature: None,
+            target: None,
+            position: None,
+            panning: false,
+        }
+    }
+
+    /// Returns the crop for the next frame of the sequence.
+    pub fn push_frame<I: Image + ResizableImage<RI>, RI: Image>(
+        &mut self,
+        frame: &I,
+    ) -> Result<Crop, Error> {
+        if frame.width() == 0 || frame.height() == 0 {
+            return Err(Error::ZeroSizedImage);
+        }
+
+        let map = self.analyzer.frame_map(frame, &self.analyzer.monitor())?;
+        let signature = signature(&map);
+        let scene_cut = match self.signature {
+            Some(ref previous) => {
+                difference(previous, &signature) > self.settings.scene_cut_threshold
+            }
+            None => true,
+        };
+        self.signature = Some(signature);
+
+        let keyframe =
+            scene_cut || self.frame_index % self.settings.keyframe_interval.get() as u64 == 0;
+        let target = if keyframe {
+            Some(
+                self.analyzer
+                    .find_best_crop_for_map(frame, self.width, self.height, |_| Ok(map))?
+                    .crop,
+            )
+        } else {
+            None
+        };
+
+        Ok(self
+            .push_target(target.as_ref(), scene_cut)
+            .expect("The first frame is always a keyframe"))
+    }
+
+    /// Returns crops for all the frames.
+    pub fn track<'f, I, RI, F>(&mut self, frames: F) -> Result<Vec<Crop>, Error>
+    where
+        I: Image + ResizableImage<RI> + 'f,
+        RI: Image,
+        F: IntoIterator<Item = &'f I>,
+    {
+        frames.into_iter().map(|f| self.push_frame(f)).collect()
+    }
+
+    /// Smoothing only, for callers that analyse frames themselves.
+    ///
+    /// `target` is the best crop of a keyframe or `None` for frames in
+    /// between. Returns `None` until the first target is known.
+    pub fn push_target(&mut self, target: Option<&Crop>, scene_cut: bool) -> Option<Crop> {
+        self.frame_index += 1;
+
+        let resized = match (target, &self.target) {
+            (Some(new), Some(old)) => new.width != old.width || new.height != old.height,
+            _ => false,
+        };
+        if let Some(target) = target {
+            self.target = Some(target.clone());
+        }
+        let target = self.target.clone()?;
+        let (tx, ty) = (target.x as f64, target.y as f64);
+
+        let (x, y) = match self.position {
+            Some(position) if !scene_cut && !resized => position,
+            _ => {
+                self.position = Some((tx, ty));
+                self.panning = false;
+                return Some(target);
+            }
+        };
+
+        let (dx, dy) = (tx - x, ty - y);
+        let dead_x = self.settings.dead_zone * target.width as f64;
+        let dead_y = self.settings.dead_zone * target.height as f64;
+        if dx.abs() > dead_x || dy.abs() > dead_y {
+            self.panning = true;
+        }
+
+        let position = if !self.panning {
+            (x, y)
+        } else if dx.abs() < 1.0 && dy.abs() < 1.0 {
+            self.panning = false;
+            (tx, ty)
+        } else {
+            let max_x = self.settings.max_pan_speed * target.width as f64;
+            let max_y = self.settings.max_pan_speed * target.height as f64;
+            let step = |d: f64, max: f64| {
+                let eased = d * self.settings.easing;
+                // Never get stuck crawling towards the target
+                let eased = if eased.abs() < 1.0 {
+                    d.signum() * d.abs().min(1.0)
+                } else {
+                    eased
+                };
+                eased.clamp(-max.max(1.0), max.max(1.0))
+            };
+            (x + step(dx, max_x), y + step(dy, max_y))
+        };
+        self.position = Some(position);
+
+        Some(Crop {
+            x: position.0.round() as u32,
+            y: position.1.round() as u32,
+            width: target.width,
+            height: target.height,
+        })
+    }
+}
+
+// Feature channels of the frame analysis down-sampled to a tiny map
+fn signature(map: &ImageMap) -> ImageMap {
+    let factor = (f64::max(map.width as f64, map.height as f64) / SIGNATURE_SIZE).ceil() as u32;
+    let factor = factor.min(map.width.min(map.height)).max(1);
+    map.clone().down_sample(factor, &Monitor::default())
+}
+
+fn difference(a: &ImageMap, b: &ImageMap) -> f64 {
+    if a.width != b.width || a.height != b.height {
+        return 1.0;
+    }
+
+    let mut sum = 0.0;
+    for y in 0..a.height {
+        for x in 0..a.width {
+            let (ca, cb) = (a.get(x, y), b.get(x, y));
+            sum += (ca.r as f64 - cb.r as f64).abs()
+                + (ca.g as f64 - cb.g as f64).abs()
+                + (ca.b as f64 - cb.b as f64).abs();
+        }
+    }
+
+    sum / (3.0 * 255.0 * a.width as f64 * a.height as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RgbBuffer, SkinModel, RGB};
+    use super::*;
+
+    const SKIN: RGB = RGB {
+        r: 255,
+        g: 200,
+        b: 159,
+    };
+
+    fn frame<G: Fn(u32, u32) -> RGB>(generate: G) -> RgbBuffer<'static> {
+        RgbBuffer::from_fn(160, 60, generate)
+    }
+
+    fn skin_at(center: u32) -> RgbBuffer<'static> {
+        frame(|x, y| {
+            if x + 10 >= center && x < center + 10 && (20..40).contains(&y) {
+                SKIN
+            } else {
+                RGB::new(40, 60, 40)
+            }
+        })
+    }
+
+    fn cropper(settings: VideoCropSettings) -> VideoCropper {
+        let size = NonZeroU32::new(1).unwrap();
+        VideoCropper::new(CropSettings::default(), settings, size, size)
+    }
+
+    fn crop_at(x: u32) -> Crop {
+        Crop {
+            x,
+            y: 0,
+            width: 100,
+            height: 100,
+        }
+    }
+
+    #[test]
+    fn first_target_is_used_as_is() {
+        let mut cropper = cropper(VideoCropSettings::default());
+
+        assert_eq!(cropper.push_target(None, false), None);
+        assert_eq!(
+            cropper.push_target(Some(&crop_at(50)), false),
+            Some(crop_at(50))
+        );
+    }
+
+    #[test]
+    fn small_moves_stay_within_dead_zone() {
+        let mut cropper = cropper(VideoCropSettings::default());
+
+        cropper.push_target(Some(&crop_at(50)), false);
+        let crop = cropper.push_target(Some(&crop_at(58)), false);
+
+        assert_eq!(crop, Some(crop_at(50)));
+    }
+
+    #[test]
+    fn pans_with_limited_speed_until_target_is_reached() {
+        let mut cropper = cropper(VideoCropSettings::default());
+        cropper.push_target(Some(&crop_at(0)), false);
+
+        let mut previous = 0;
+        let mut frames = 0;
+        let mut target = Some(crop_at(200));
+        loop {
+            let crop = cropper.push_target(target.take().as_ref(), false).unwrap();
+            assert!(crop.x >= previous);
+            assert!(crop.x - previous <= 2);
+            previous = crop.x;
+            frames += 1;
+            if crop.x == 200 {
+                break;
+            }
+            assert!(frames < 1000);
+        }
+        assert!(frames >= 99);
+    }
+
+    #[test]
+    fn scene_cut_jumps_to_target() {
+        let mut cropper = cropper(VideoCropSettings::default());
+
+        cropper.push_target(Some(&crop_at(0)), false);
+        let crop = cropper.push_target(Some(&crop_at(200)), true);
+
+        assert_eq!(crop, Some(crop_at(200)));
+    }
+
+    fn frame_signature(settings: CropSettings, frame: &RgbBuffer) -> ImageMap {
+        let analyzer = Analyzer::new(settings);
+        signature(&analyzer.frame_map(frame, &analyzer.monitor()).unwrap())
+    }
+
+    #[test]
+    fn signature_difference_detects_scene_cuts() {
+        let signature = |frame: RgbBuffer| frame_signature(CropSettings::default(), &frame);
+        let a = signature(skin_at(30));
+        let b = signature(skin_at(32));
+        let c = signature(frame(|_, _| RGB::new(255, 0, 255)));
+
+        let threshold = VideoCropSettings::default().scene_cut_threshold;
+        assert!(difference(&a, &b) < threshold);
+        assert!(difference(&a, &c) > threshold);
+    }
+
+    #[test]
+    fn signature_uses_the_crop_settings() {
+        let dark_skin = frame(|x, y| {
+            if (70..90).contains(&x) && (20..40).contains(&y) {
+                RGB::new(96, 62, 44)
+            } else {
+                RGB::new(40, 60, 40)
+            }
+        });
+        let skin = |skin_model| {
+            let settings = CropSettings {
+                skin_model,
+                ..CropSettings::default()
+            };
+            frame_signature(settings, &dark_skin)
+                .skin
+                .iter()
+                .sum::<f32>()
+        };
+
+        assert!(skin(SkinModel::Chroma) > 1.5 * skin(SkinModel::Reference));
+    }
+
+    #[test]
+    fn follows_subject_smoothly() {
+        let settings = VideoCropSettings {
+            keyframe_interval: NonZeroU32::new(1).unwrap(),
+            ..VideoCropSettings::default()
+        };
+        let size = NonZeroU32::new(60).unwrap();
+        let mut cropper = VideoCropper::new(CropSettings::default(), settings, size, size);
+        let frames: Vec<_> = (0..40).map(|i| skin_at(30 + i * 2)).collect();
+
+        let crops = cropper.track(&frames).unwrap();
+
+        assert_eq!(crops.len(), 40);
+        for pair in crops.windows(2) {
+            assert!((pair[1].x as i64 - pair[0].x as i64).abs() <= 2);
+        }
+        assert!(crops[39].x > crops[0].x);
+    }
+
+    #[test]
+    fn scene_cut_in_frames_is_detected() {
+        let size = NonZeroU32::new(60).unwrap();
+        let mut cropper =
+            VideoCropper::new(CropSettings::default(), Default::default(), size, size);
+        let cut = frame(|x, y| {
+            if x > 110 && (20..40).contains(&y) {
+                SKIN
+            } else {
+                RGB::new(20, 20, 200)
+            }
+        });
+
+        let first = cropper.push_frame(&skin_at(20)).unwrap();
+        let second = cropper.push_frame(&cut).unwrap();
+
+        let best = Analyzer::new(CropSettings::default())
+            .find_best_crop(&cut, size, size)
+            .unwrap()
+            .crop;
+        assert_eq!(second, best);
+        assert!(second.x > first.x + 2);
+    }
+}
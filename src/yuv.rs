@@ -0,0 +1,428 @@
+use super::math::bounds;
+use super::resize::area_average;
+use super::{ColorModel, Error, Image, ResizableImage, RGB};
+use std::borrow::Cow;
+
+// Sum of the coefficients of `RGB::cie`, so gray levels get the same lightness
+const CIE_GRAY_SCALE: f64 = 0.0722 + 0.7152 + 0.5126;
+
+/// Matrix used to convert Y'CbCr to RGB.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum YuvMatrix {
+    Bt601,
+    Bt709,
+}
+
+impl YuvMatrix {
+    // Coefficients for R from Cr, G from Cb and Cr, and B from Cb
+    fn coefficients(self) -> (f64, f64, f64, f64) {
+        match self {
+            YuvMatrix::Bt601 => (1.402, 0.344_136, 0.714_136, 1.772),
+            YuvMatrix::Bt709 => (1.5748, 0.187_324, 0.468_124, 1.8556),
+        }
+    }
+}
+
+/// Range of the stored sample values.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum YuvRange {
+    /// Y in 16..=235, chroma in 16..=240, as produced by most video decoders
+    Limited,
+    /// All samples use 0..=255
+    Full,
+}
+
+/// A single plane of 8-bit samples whose rows are `stride` bytes apart.
+#[derive(Debug, Clone)]
+pub struct YuvPlane<'a> {
+    data: Cow<'a, [u8]>,
+    stride: usize,
+}
+
+impl<'a> YuvPlane<'a> {
+    pub fn new<D>(data: D, stride: usize) -> YuvPlane<'a>
+    where
+        D: Into<Cow<'a, [u8]>>,
+    {
+        YuvPlane {
+            data: data.into(),
+            stride,
+        }
+    }
+
+    fn sample(&self, x: u32, y: u32) -> u8 {
+        self.data[y as usize * self.stride + x as usize]
+    }
+
+    fn validate(&self, row_length: usize, rows: u32) -> Result<(), Error> {
+        if self.stride < row_length {
+            return Err(Error::InvalidStride);
+        }
+        if rows > 0 {
+            let length = (self.stride.checked_mul(rows as usize - 1))
+                .and_then(|rows| rows.checked_add(row_length))
+                .ok_or(Error::BufferTooSmall)?;
+            if self.data.len() < length {
+                return Err(Error::BufferTooSmall);
+            }
+        }
+        Ok(())
+    }
+
+    fn into_owned(self) -> YuvPlane<'static> {
+        YuvPlane {
+            data: Cow::Owned(self.data.into_owned()),
+            stride: self.stride,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Chroma<'a> {
+    // I420: separate U and V planes
+    Planar { u: YuvPlane<'a>, v: YuvPlane<'a> },
+    // NV12: a single plane of interleaved U and V samples
+    Interleaved(YuvPlane<'a>),
+}
+
+/// 4:2:0 planar YUV frame (I420 or NV12).
+///
+/// Luma is used for edge detection as is, chroma is only converted to RGB
+/// for skin and saturation detection.
+#[derive(Debug, Clone)]
+pub struct YuvBuffer<'a> {
+    width: u32,
+    height: u32,
+    matrix: YuvMatrix,
+    range: YuvRange,
+    luma: YuvPlane<'a>,
+    chroma: Chroma<'a>,
+}
+
+impl<'a> YuvBuffer<'a> {
+    pub fn i420(
+        width: u32,
+        height: u32,
+        y: YuvPlane<'a>,
+        u: YuvPlane<'a>,
+        v: YuvPlane<'a>,
+    ) -> Result<Self, Error> {
+        let (chroma_width, chroma_height) = chroma_dimensions(width, height);
+        y.validate(width as usize, height)?;
+        u.validate(chroma_width as usize, chroma_height)?;
+        v.validate(chroma_width as usize, chroma_height)?;
+
+        Ok(YuvBuffer::from_planes(
+            width,
+            height,
+            y,
+            Chroma::Planar { u, v },
+        ))
+    }
+
+    pub fn nv12(width: u32, height: u32, y: YuvPlane<'a>, uv: YuvPlane<'a>) -> Result<Self, Error> {
+        let (chroma_width, chroma_height) = chroma_dimensions(width, height);
+        y.validate(width as usize, height)?;
+        uv.validate(2 * chroma_width as usize, chroma_height)?;
+
+        Ok(YuvBuffer::from_planes(
+            width,
+            height,
+            y,
+            Chroma::Interleaved(uv),
+        ))
+    }
+
+    fn from_planes(width: u32, height: u32, luma: YuvPlane<'a>, chroma: Chroma<'a>) -> Self {
+        YuvBuffer {
+            width,
+            height,
+            matrix: YuvMatrix::Bt601,
+            range: YuvRange::Limited,
+            luma,
+            chroma,
+        }
+    }
+
+    /// Defaults to `YuvMatrix::Bt601`
+    pub fn with_matrix(self, matrix: YuvMatrix) -> Self {
+        YuvBuffer { matrix, ..self }
+    }
+
+    /// Defaults to `YuvRange::Limited`
+    pub fn with_range(self, range: YuvRange) -> Self {
+        YuvBuffer { range, ..self }
+    }
+
+    pub fn into_owned(self) -> YuvBuffer<'static> {
+        let chroma = match self.chroma {
+            Chroma::Planar { u, v } => Chroma::Planar {
+                u: u.into_owned(),
+                v: v.into_owned(),
+            },
+            Chroma::Interleaved(uv) => Chroma::Interleaved(uv.into_owned()),
+        };
+
+        YuvBuffer {
+            width: self.width,
+            height: self.height,
+            matrix: self.matrix,
+            range: self.range,
+            luma: self.luma.into_owned(),
+            chroma,
+        }
+    }
+
+    fn chroma_samples(&self, x: u32, y: u32) -> (u8, u8) {
+        let (cx, cy) = (x / 2, y / 2);
+        match self.chroma {
+            Chroma::Planar { ref u, ref v } => (u.sample(cx, cy), v.sample(cx, cy)),
+            Chroma::Interleaved(ref uv) => (uv.sample(2 * cx, cy), uv.sample(2 * cx + 1, cy)),
+        }
+    }
+
+    // Luma expanded to 0..=255
+    fn full_range_luma(&self, x: u32, y: u32) -> f64 {
+        let luma = self.luma.sample(x, y) as f64;
+        match self.range {
+            YuvRange::Limited => (luma - 16.0) * 255.0 / 219.0,
+            YuvRange::Full => luma,
+        }
+    }
+
+    // Chroma centered around zero and expanded to -127.5..=127.5
+    fn full_range_chroma(&self, sample: u8) -> f64 {
+        let chroma = sample as f64 - 128.0;
+        match self.range {
+            YuvRange::Limited => chroma * 255.0 / 224.0,
+            YuvRange::Full => chroma,
+        }
+    }
+}
+
+fn chroma_dimensions(width: u32, height: u32) -> (u32, u32) {
+    (width / 2 + width % 2, height / 2 + height % 2)
+}
+
+impl<'a> Image for YuvBuffer<'a> {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn get(&self, x: u32, y: u32) -> RGB {
+        let (u, v) = self.chroma_samples(x, y);
+        let luma = self.full_range_luma(x, y);
+        let cb = self.full_range_chroma(u);
+        let cr = self.full_range_chroma(v);
+        let (r_cr, g_cb, g_cr, b_cb) = self.matrix.coefficients();
+
+        RGB::new(
+            bounds(luma + r_cr * cr),
+            bounds(luma - g_cb * cb - g_cr * cr),
+            bounds(luma + b_cb * cb),
+        )
+    }
+
+    fn cie(&self, x: u32, y: u32) -> f64 {
+        self.full_range_luma(x, y).clamp(0.0, 255.0) * CIE_GRAY_SCALE
+    }
+
+    // BT.709 luma is the Rec. 709 lightness, other matrices and linear light
+    // need the converted color
+    fn lightness(&self, x: u32, y: u32, model: ColorModel) -> f64 {
+        match (model, self.matrix) {
+            (ColorModel::JsCompat, _) => self.cie(x, y),
+            (ColorModel::Rec709, YuvMatrix::Bt709) => self.full_range_luma(x, y).clamp(0.0, 255.0),
+            (model, _) => model.lightness(self.get(x, y)),
+        }
+    }
+}
+
+impl<'a> ResizableImage<YuvBuffer<'static>> for YuvBuffer<'a> {
+    /// Produces an owned I420 frame with the same matrix and range, each
+    /// plane is resized with the area-average filter.
+    fn resize(&self, width: u32, height: u32) -> YuvBuffer<'static> {
+        let (chroma_width, chroma_height) = chroma_dimensions(width, height);
+        let (src_chroma_width, src_chroma_height) = chroma_dimensions(self.width, self.height);
+
+        let luma = resize_plane(self.width, self.height, width, height, |x, y| {
+            self.luma.sample(x, y)
+        });
+        let u = resize_plane(
+            src_chroma_width,
+            src_chroma_height,
+            chroma_width,
+            chroma_height,
+            |x, y| self.chroma_samples(2 * x, 2 * y).0,
+        );
+        let v = resize_plane(
+            src_chroma_width,
+            src_chroma_height,
+            chroma_width,
+            chroma_height,
+            |x, y| self.chroma_samples(2 * x, 2 * y).1,
+        );
+
+        YuvBuffer {
+            width,
+            height,
+            matrix: self.matrix,
+            range: self.range,
+            luma: YuvPlane::new(luma, width as usize),
+            chroma: Chroma::Planar {
+                u: YuvPlane::new(u, chroma_width as usize),
+                v: YuvPlane::new(v, chroma_width as usize),
+            },
+        }
+    }
+}
+
+fn resize_plane<G>(src_width: u32, src_height: u32, width: u32, height: u32, get: G) -> Vec<u8>
+where
+    G: Fn(u32, u32) -> u8,
+{
+    let mut plane = Vec::with_capacity(width as usize * height as usize);
+    area_average(
+        src_width,
+        src_height,
+        width,
+        height,
+        |x, y| [get(x, y) as f64, 0.0, 0.0, 0.0],
+        |_, _, [sample, _, _, _]| plane.push(bounds(sample)),
+    );
+    plane
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x2 I420 frame: left half is limited range white, right half is pure red
+    fn i420_frame() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let y = vec![235, 235, 81, 81, 235, 235, 81, 81];
+        let u = vec![128, 90];
+        let v = vec![128, 240];
+        (y, u, v)
+    }
+
+    #[test]
+    fn i420_converts_to_rgb() {
+        let (y, u, v) = i420_frame();
+        let image = YuvBuffer::i420(
+            4,
+            2,
+            YuvPlane::new(&y[..], 4),
+            YuvPlane::new(&u[..], 2),
+            YuvPlane::new(&v[..], 2),
+        )
+        .unwrap();
+
+        assert_eq!(image.get(0, 0), RGB::new(255, 255, 255));
+        assert_eq!(image.get(1, 1), RGB::new(255, 255, 255));
+        assert_eq!(image.get(3, 1), RGB::new(254, 0, 0));
+    }
+
+    #[test]
+    fn nv12_matches_i420() {
+        let (y, u, v) = i420_frame();
+        let uv = vec![u[0], v[0], u[1], v[1]];
+        let i420 = YuvBuffer::i420(
+            4,
+            2,
+            YuvPlane::new(&y[..], 4),
+            YuvPlane::new(&u[..], 2),
+            YuvPlane::new(&v[..], 2),
+        )
+        .unwrap();
+        let nv12 = YuvBuffer::nv12(4, 2, YuvPlane::new(&y[..], 4), YuvPlane::new(uv, 4)).unwrap();
+
+        for y in 0..2 {
+            for x in 0..4 {
+                assert_eq!(nv12.get(x, y), i420.get(x, y));
+                assert_eq!(nv12.cie(x, y), i420.cie(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn luma_is_used_as_lightness() {
+        let y = vec![16, 235];
+        let uv = vec![128, 128];
+        let image = YuvBuffer::nv12(2, 1, YuvPlane::new(y, 2), YuvPlane::new(uv, 2)).unwrap();
+
+        assert_eq!(image.cie(0, 0), 0.0);
+        assert_eq!(image.cie(1, 0), RGB::new(255, 255, 255).cie());
+
+        let image = image.with_matrix(YuvMatrix::Bt709);
+        assert_eq!(image.lightness(0, 0, ColorModel::Rec709), 0.0);
+        assert_eq!(image.lightness(1, 0, ColorModel::Rec709), 255.0);
+        assert_eq!(image.lightness(1, 0, ColorModel::JsCompat), image.cie(1, 0));
+    }
+
+    #[test]
+    fn full_range_bt709() {
+        let y = vec![128];
+        let u = vec![128];
+        let v = vec![255];
+        let image = YuvBuffer::i420(
+            1,
+            1,
+            YuvPlane::new(y, 1),
+            YuvPlane::new(u, 1),
+            YuvPlane::new(v, 1),
+        )
+        .unwrap()
+        .with_matrix(YuvMatrix::Bt709)
+        .with_range(YuvRange::Full);
+
+        assert_eq!(image.get(0, 0), RGB::new(255, 69, 128));
+    }
+
+    #[test]
+    fn rejects_too_small_planes() {
+        let result = YuvBuffer::nv12(
+            4,
+            4,
+            YuvPlane::new(vec![0; 16], 4),
+            YuvPlane::new(vec![0; 3], 4),
+        );
+
+        assert_eq!(Error::BufferTooSmall, result.unwrap_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_strides() {
+        let result = YuvBuffer::nv12(
+            1,
+            4,
+            YuvPlane::new(vec![0; 4], usize::MAX / 2),
+            YuvPlane::new(vec![0; 4], 2),
+        );
+
+        assert_eq!(Error::BufferTooSmall, result.unwrap_err());
+    }
+
+    #[test]
+    fn resize_keeps_yuv() {
+        let (y, u, v) = i420_frame();
+        let image = YuvBuffer::i420(
+            4,
+            2,
+            YuvPlane::new(y, 4),
+            YuvPlane::new(u, 2),
+            YuvPlane::new(v, 2),
+        )
+        .unwrap();
+
+        let resized = image.resize(2, 1);
+
+        assert_eq!(resized.width(), 2);
+        assert_eq!(resized.height(), 1);
+        assert_eq!(resized.cie(0, 0), image.cie(0, 0));
+        assert_eq!(resized.cie(1, 0), image.cie(3, 0));
+    }
+}
@@ -1,3 +1,6 @@
+// Kept as ported from smartcrop.js, newer lints are not applied
+#![allow(clippy::unnecessary_cast, clippy::needless_return)]
+
 extern crate rand;
 #[macro_use]
 extern crate proptest;
@@ -126,6 +129,36 @@ fn find_best_crop_test() {
     assert_eq!(crop.score.total, -0.017031057622565366);
 }
 
+#[test]
+fn find_best_crop_on_raw_buffer_test() {
+    let mut data = Vec::new();
+    for _ in 0..8 {
+        for x in 0..24 {
+            let RGB { r, g, b } = if x < 9 {
+                RGB { r: 0, g: 255, b: 0 }
+            } else if x < 16 {
+                SKIN
+            } else {
+                WHITE
+            };
+            data.extend_from_slice(&[b, g, r, 255]);
+        }
+    }
+    let image = RgbBuffer::new(&data[..], 24, 8, PixelLayout::Bgra).unwrap();
+    let analyzer = Analyzer::new(CropSettings::default());
+
+    let crop = analyzer
+        .find_best_crop(
+            &image,
+            NonZeroU32::new(8).unwrap(),
+            NonZeroU32::new(8).unwrap(),
+        )
+        .unwrap();
+
+    assert_eq!(crop.crop.x, 16);
+    assert_eq!(crop.score.total, -0.017031057622565366);
+}
+
 #[test]
 fn find_best_crop_wrong_rounding_test() {
     let image = TestImage::new_from_fn(640, 426, |_, _| WHITE);